@@ -3,14 +3,24 @@ use std::thread;
 use crate::client_handler::Client;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use crate::channels::Channel;
+use crate::channels::{Channel, channel_key};
 use crate::postman::{PostmanMessage, send_message};
+use crate::protocol::{Reply, Numeric};
+
+pub enum BroadcastKind {
+    // Message relayed by the server (JOIN, PART, ...)
+    Server,
+    Privmsg,
+    // Never answered with an error (RFC 2812 3.3.2)
+    Notice
+}
 
 pub struct BroadcastMessage {
     pub content: String,
     pub sender: Client,
     pub channel: String,
-    pub send_to_sender: bool
+    pub send_to_sender: bool,
+    pub kind: BroadcastKind
 }
 
 // Sends a message to all clients connected in a given channel
@@ -40,18 +50,36 @@ pub fn start_broadcaster_thread(
             };
 
             // Get the IRC channel on which the message will be sent
-            let channel = match channels.get(&*msg.channel.clone()) {
+            let channel = match channels.get(&channel_key(&msg.channel)) {
                 Some(channel) => channel,
                 _ => {
                     println!("Channel {} doesn't exist", msg.channel.clone());
+
+                    if let BroadcastKind::Privmsg = msg.kind {
+                        let reply = Reply::new(Numeric::ErrNoSuchNick, &msg.sender.nickname)
+                            .param(&msg.channel)
+                            .trailing("No such nick/channel");
+                        send_message(PostmanMessage { client: msg.sender, content: reply.to_line() }, postman_tx.clone());
+                    }
                     continue
                 }
             };
 
+            // +n and +m only apply to messages sent by users
+            if !matches!(msg.kind, BroadcastKind::Server) && !channel.can_send(&msg.sender) {
+                if let BroadcastKind::Privmsg = msg.kind {
+                    let reply = Reply::new(Numeric::ErrCannotSendToChan, &msg.sender.nickname)
+                        .param(&channel.name)
+                        .trailing("Cannot send to channel");
+                    send_message(PostmanMessage { client: msg.sender, content: reply.to_line() }, postman_tx.clone());
+                }
+                continue
+            }
+
             // Sends the message to all clients connected in the IRC channel
             for client in channel.clients.iter() {
                 // Skip sending message to sender if wanted
-                if *client == msg.sender && msg.send_to_sender == false {
+                if *client == msg.sender && !msg.send_to_sender {
                     continue;
                 }
 
@@ -72,7 +100,6 @@ pub fn send_broadcast_message(message: BroadcastMessage, broadcast_tx: Sender<Br
         Ok(_) => {},
         Err(e) => {
             println!("Unable to send message to broadcast channel: {:?}", e);
-            return
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,115 @@
+// Rules used to compare nicknames and channel names, advertised with CASEMAPPING
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaseMapping {
+    Ascii,
+    // "{}|~" are the lower case of "[]\^" (RFC 1459 2.2)
+    Rfc1459,
+    // Same as rfc1459, without "~" and "^"
+    StrictRfc1459
+}
+
+impl CaseMapping {
+    pub fn from_name(name: &str) -> Option<CaseMapping> {
+        match name {
+            "ascii" => Some(CaseMapping::Ascii),
+            "rfc1459" => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CaseMapping::Ascii => "ascii",
+            CaseMapping::Rfc1459 => "rfc1459",
+            CaseMapping::StrictRfc1459 => "strict-rfc1459"
+        }
+    }
+
+    pub fn to_lower(self, input: &str) -> String {
+        input.chars()
+            .map(|c| match (self, c) {
+                (CaseMapping::Rfc1459, '[') | (CaseMapping::StrictRfc1459, '[') => '{',
+                (CaseMapping::Rfc1459, ']') | (CaseMapping::StrictRfc1459, ']') => '}',
+                (CaseMapping::Rfc1459, '\\') | (CaseMapping::StrictRfc1459, '\\') => '|',
+                (CaseMapping::Rfc1459, '^') => '~',
+                _ => c.to_ascii_lowercase()
+            })
+            .collect()
+    }
+
+    pub fn equals(self, a: &str, b: &str) -> bool {
+        self.to_lower(a) == self.to_lower(b)
+    }
+
+    // Wildcard matching of hostmasks: "*" matches any sequence of characters, "?" a single one
+    pub fn matches(self, mask: &str, input: &str) -> bool {
+        let mask: Vec<char> = self.to_lower(mask).chars().collect();
+        let input: Vec<char> = self.to_lower(input).chars().collect();
+
+        let (mut m, mut i) = (0, 0);
+        // Position of the last "*" in the mask, and of the input when it was met
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while i < input.len() {
+            if m < mask.len() && (mask[m] == '?' || mask[m] == input[i]) {
+                m += 1;
+                i += 1;
+            } else if m < mask.len() && mask[m] == '*' {
+                backtrack = Some((m, i));
+                m += 1;
+            } else if let Some((star, matched)) = backtrack {
+                // Let the last "*" match one more character
+                m = star + 1;
+                i = matched + 1;
+                backtrack = Some((star, matched + 1));
+            } else {
+                return false
+            }
+        }
+
+        mask[m..].iter().all(|c| *c == '*')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_trailing_star() {
+        assert!(CaseMapping::Ascii.matches("nick!*", "nick!user@host"));
+        assert!(CaseMapping::Ascii.matches("*", ""));
+        assert!(!CaseMapping::Ascii.matches("nick!*", "other!user@host"));
+    }
+
+    #[test]
+    fn backtracks_over_stars() {
+        let mask = "*!*@*.example.com";
+        assert!(CaseMapping::Ascii.matches(mask, "nick!user@a.b.example.com"));
+        assert!(CaseMapping::Ascii.matches(mask, "nick!user@example.example.com"));
+        assert!(!CaseMapping::Ascii.matches(mask, "nick!user@example.com"));
+        assert!(!CaseMapping::Ascii.matches(mask, "nick!user@a.example.com.evil.org"));
+    }
+
+    #[test]
+    fn matches_single_characters() {
+        assert!(CaseMapping::Ascii.matches("ni?k!*@*", "nick!user@host"));
+        assert!(!CaseMapping::Ascii.matches("ni?k!*@*", "nik!user@host"));
+        assert!(!CaseMapping::Ascii.matches("nick?", "nick"));
+    }
+
+    #[test]
+    fn folds_rfc1459_characters() {
+        assert!(CaseMapping::Rfc1459.matches("[Nick]^*", "{nick}~away"));
+        assert!(CaseMapping::Rfc1459.equals("a\\b", "A|B"));
+        assert!(!CaseMapping::Ascii.matches("[nick]", "{nick}"));
+    }
+
+    #[test]
+    fn strict_rfc1459_keeps_caret() {
+        assert!(CaseMapping::StrictRfc1459.matches("[nick]", "{NICK}"));
+        assert!(!CaseMapping::StrictRfc1459.matches("nick^", "nick~"));
+        assert!(!CaseMapping::StrictRfc1459.equals("a^", "a~"));
+    }
+}
@@ -2,31 +2,230 @@ use std::sync::mpsc::{Receiver, Sender};
 use crate::client_handler::Client;
 use std::thread;
 use std::io::{BufWriter, Write};
-use crate::protocol::{join_message, join_header, join_members, join_end_members, part_msg};
+use crate::protocol::{join_message, join_header, join_members, join_end_members, part_msg, topic_msg, channel_mode_is, mode_msg, kick_msg, invite_msg, quit_msg, need_more_params, mask_list, list_start, list_entry, list_end};
+use crate::protocol::{who_reply, end_of_who, whois_channels, whois_idle, end_of_whois};
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
-use crate::broadcast::{BroadcastMessage, send_broadcast_message};
+use crate::broadcast::{BroadcastMessage, BroadcastKind, send_broadcast_message};
 use crate::postman::{PostmanMessage, send_message};
+use crate::registration::{RegistrationMessage, RegistrationAction, send_registration_message};
+use crate::protocol::{Reply, Numeric};
+use crate::config;
+use crate::clock::unix_timestamp;
+use crate::modes::{ChannelModes, ModeChange, MaskEntry, CHANNEL_FLAG_MODES, CHANNEL_LIST_MODES, MEMBERSHIP_MODES, parse_mode_changes, format_mode_changes, membership_modes, membership_rank, normalize_mask};
+use uuid::Uuid;
+
+pub enum ChannelAction {
+    Join,
+    Leave,
+    // Called when a client unregisters or has a connection error, body holds the QUIT reason
+    LeaveAll,
+    // The client has a new nickname, body holds the NICK message to relay
+    ChangeNickname,
+    // Queries the topic, or changes it to body if given
+    Topic,
+    // Queries the modes, or changes them with the given modestring and parameters
+    Mode(Vec<String>),
+    // Removes the given nicknames from the channel, body holds the reason
+    Kick(Vec<String>),
+    // Invites the given client to the channel
+    Invite(Client),
+    // Sends the members of the channel, or of every visible channel
+    Names,
+    // Sends the visible channels matching the given names and ELIST filters
+    List(Vec<String>),
+    // Sends the members of the channel, with the WHOX fields if given
+    Who(Option<String>),
+    // Hands the WHO of the mask held in body to the registration thread, with the ids of the
+    // clients sharing a channel with the client, and the WHOX fields if given
+    WhoMask(Option<String>),
+    // Ends the WHOIS of the given client with its channels and idle time
+    Whois(Client)
+}
+
+// ELIST filters of a LIST command (M, T and U extensions)
+enum ListFilter {
+    // "#rust*": channel name mask
+    Mask(String),
+    // ">n" and "<n": more or fewer than n members
+    MoreUsers(usize),
+    FewerUsers(usize),
+    // "T>n" and "T<n": topic changed more or less than n minutes ago
+    TopicOlder(u64),
+    TopicNewer(u64)
+}
+
+impl ListFilter {
+    fn parse(filter: &str) -> ListFilter {
+        let number = |value: &str| value.parse::<u64>().ok();
+
+        let parsed = if let Some(count) = filter.strip_prefix('>') {
+            number(count).map(|count| ListFilter::MoreUsers(count as usize))
+        } else if let Some(count) = filter.strip_prefix('<') {
+            number(count).map(|count| ListFilter::FewerUsers(count as usize))
+        } else if let Some(minutes) = filter.strip_prefix("T>") {
+            number(minutes).map(ListFilter::TopicOlder)
+        } else if let Some(minutes) = filter.strip_prefix("T<") {
+            number(minutes).map(ListFilter::TopicNewer)
+        } else {
+            None
+        };
+
+        parsed.unwrap_or_else(|| ListFilter::Mask(String::from(filter)))
+    }
+
+    fn accepts(&self, channel: &Channel, now: u64) -> bool {
+        let topic_age = now.saturating_sub(channel.topic_time) / 60;
+
+        match self {
+            ListFilter::Mask(mask) => config::get().casemapping.matches(mask, &channel.name),
+            ListFilter::MoreUsers(count) => channel.clients.len() > *count,
+            ListFilter::FewerUsers(count) => channel.clients.len() < *count,
+            ListFilter::TopicOlder(minutes) => channel.topic_time > 0 && topic_age > *minutes,
+            ListFilter::TopicNewer(minutes) => channel.topic_time > 0 && topic_age < *minutes
+        }
+    }
+}
 
 pub struct ChannelMessage {
     pub client: Client,
+    // None for Leave means every channel the client is in
     pub channel: Option<String>,
     pub body: Option<String>,
-    pub leave: bool,
-    pub all_channels: bool
+    pub action: ChannelAction
 }
 
 #[derive(Clone)]
 pub struct Channel {
     pub name: String,
     pub description: String,
-    pub clients: Vec<Client>
+    // Who changed the topic and when
+    pub topic_setter: String,
+    pub topic_time: u64,
+    pub clients: Vec<Client>,
+    // Membership modes ("qohv") of each member, by client id
+    pub privileges: HashMap<Uuid, String>,
+    // Persistent channels are kept when their last member leaves
+    pub persistent: bool,
+    pub modes: ChannelModes,
+    pub created_at: u64,
+    // Ids of the invited clients, allowed to join once even if the channel is invite only
+    pub invites: Vec<Uuid>,
+    // Hostmasks set with +b, +e and +I
+    pub bans: Vec<MaskEntry>,
+    pub ban_exceptions: Vec<MaskEntry>,
+    pub invite_exceptions: Vec<MaskEntry>
+}
+
+impl Channel {
+    pub fn new(name: &str) -> Channel {
+        Channel {
+            name: String::from(name),
+            description: String::new(),
+            topic_setter: String::new(),
+            topic_time: 0,
+            clients: Vec::new(),
+            privileges: HashMap::new(),
+            persistent: false,
+            modes: ChannelModes::from_flags(&config::get().default_channel_modes),
+            created_at: unix_timestamp(),
+            invites: Vec::new(),
+            bans: Vec::new(),
+            ban_exceptions: Vec::new(),
+            invite_exceptions: Vec::new()
+        }
+    }
+
+    // Whether the client can send PRIVMSG or NOTICE to the channel (+n, +m and +b)
+    pub fn can_send(&self, client: &Client) -> bool {
+        if self.modes.no_external && !self.clients.contains(client) {
+            return false
+        }
+
+        if self.modes.moderated && !self.has_privilege(client, 'v') {
+            return false
+        }
+
+        // Voiced members can speak even if banned
+        if self.is_banned(client) && !self.has_privilege(client, 'v') {
+            return false
+        }
+
+        true
+    }
+
+    // Whether the client matches a ban without matching an exception
+    pub fn is_banned(&self, client: &Client) -> bool {
+        matches_any(&self.bans, client) && !matches_any(&self.ban_exceptions, client)
+    }
+
+    // Whether the client can join while the channel is invite only
+    pub fn is_invite_excepted(&self, client: &Client) -> bool {
+        matches_any(&self.invite_exceptions, client)
+    }
+
+    // Secret channels are hidden from non members
+    pub fn is_visible_to(&self, client: &Client) -> bool {
+        !self.modes.secret || self.clients.contains(client)
+    }
+
+    // Invisible members (+i) are hidden from clients outside of the channel
+    pub fn members_visible_to(&self, client: &Client) -> Vec<&Client> {
+        let is_member = self.clients.contains(client);
+        self.clients.iter()
+            .filter(|member| is_member || !member.user_modes().invisible)
+            .collect()
+    }
+
+    // List set with the given list mode ("beI")
+    fn mask_list(&mut self, mode: char) -> Option<&mut Vec<MaskEntry>> {
+        match mode {
+            'b' => Some(&mut self.bans),
+            'e' => Some(&mut self.ban_exceptions),
+            'I' => Some(&mut self.invite_exceptions),
+            _ => None
+        }
+    }
+
+    // Whether the member has the given membership mode, or a higher one
+    pub fn has_privilege(&self, client: &Client, mode: char) -> bool {
+        let required_rank = match membership_rank(mode) {
+            Some(rank) => rank,
+            None => return false
+        };
+
+        match self.privileges.get(&client.id) {
+            Some(modes) => modes.chars()
+                .filter_map(membership_rank)
+                .any(|rank| rank <= required_rank),
+            None => false
+        }
+    }
+
+    // Prefix of the highest membership mode of the member, shown in NAMES
+    pub fn prefix(&self, client: &Client) -> String {
+        let modes = match self.privileges.get(&client.id) {
+            Some(modes) => modes,
+            None => return String::new()
+        };
+
+        match MEMBERSHIP_MODES.iter().find(|(mode, _)| modes.contains(*mode)) {
+            Some((_, prefix)) => prefix.to_string(),
+            None => String::new()
+        }
+    }
+
+    fn remove_member(&mut self, client: &Client) {
+        self.clients.retain(|c| c != client);
+        self.privileges.remove(&client.id);
+    }
 }
 
 pub fn start_channels_thread(
     channels: Arc<Mutex<HashMap<String, Channel>>>,
     channel_rx: Receiver<ChannelMessage>,
     broadcast_tx: Sender<BroadcastMessage>,
+    registration_tx: Sender<RegistrationMessage>,
     postman_tx: Sender<PostmanMessage>
 ) {
 
@@ -43,86 +242,218 @@ pub fn start_channels_thread(
                 }
             };
 
-            // User wants to leave channel
-            if change_channel_message.leave == true {
-                leave_channel(
-                    change_channel_message,
-                    broadcast_tx.clone(),
-                    postman_tx.clone(),
-                    channels.clone()
-                );
-                continue
+            match change_channel_message.action {
+                ChannelAction::Join => {
+                    join_channel(change_channel_message, broadcast_tx.clone(), postman_tx.clone(), channels.clone());
+                }
+                // User wants to leave every channel (JOIN 0)
+                ChannelAction::Leave if change_channel_message.channel.is_none() => {
+                    leave_all_channels(
+                        change_channel_message,
+                        broadcast_tx.clone(),
+                        postman_tx.clone(),
+                        channels.clone()
+                    );
+                }
+                // User wants to leave channel
+                ChannelAction::Leave => {
+                    leave_channel(
+                        change_channel_message,
+                        broadcast_tx.clone(),
+                        postman_tx.clone(),
+                        channels.clone()
+                    );
+                }
+                ChannelAction::LeaveAll => {
+                    unregister_from_all_channels(change_channel_message, channels.clone(), postman_tx.clone());
+                }
+                ChannelAction::ChangeNickname => {
+                    change_nickname(change_channel_message, postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::Topic => {
+                    change_topic(change_channel_message, broadcast_tx.clone(), postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::Mode(ref args) => {
+                    let args = args.clone();
+                    change_mode(change_channel_message, args, broadcast_tx.clone(), postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::Kick(ref nicknames) => {
+                    let nicknames = nicknames.clone();
+                    kick_members(change_channel_message, nicknames, postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::Invite(ref target) => {
+                    let target = target.clone();
+                    invite_client(change_channel_message, target, postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::Names => {
+                    send_names(change_channel_message, postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::Who(ref whox) => {
+                    let whox = whox.clone();
+                    who_channel(change_channel_message, whox, postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::WhoMask(ref whox) => {
+                    let whox = whox.clone();
+                    who_mask(change_channel_message, whox, registration_tx.clone(), channels.clone());
+                }
+                ChannelAction::Whois(ref target) => {
+                    let target = target.clone();
+                    whois_channels_of(change_channel_message, target, postman_tx.clone(), channels.clone());
+                }
+                ChannelAction::List(ref filters) => {
+                    let filters = filters.iter().map(|filter| ListFilter::parse(filter)).collect();
+                    list_channels(change_channel_message, filters, postman_tx.clone(), channels.clone());
+                }
             }
+        }
+    });
+}
 
-            let mut channels = match channels.lock() {
-                Ok(channels) => channels,
-                Err(e) => {
-                    println!("Unable to acquire channels lock: {:?}", e);
-                    continue
-                }
-            };
+fn join_channel(
+    change_channel_message: ChannelMessage,
+    broadcast_tx: Sender<BroadcastMessage>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
 
-            // User wants to join the channel
+    // User wants to join the channel
 
-            let channel_name = match change_channel_message.channel {
-                Some(channel_name) => channel_name,
-                _ => {
-                    println!("Cannot join channel without name!");
-                    return
-                }
-            };
+    let channel_name = match change_channel_message.channel {
+        Some(channel_name) => channel_name,
+        _ => {
+            println!("Cannot join channel without name!");
+            return
+        }
+    };
 
-            let channel = match channels.get_mut(&*channel_name) {
-                Some(channel) => channel,
-                _ => {
-                    println!("Channel {} doesn't exist!", channel_name);
-                    // TODO: Send back IRC error message
-                    continue
-                }
-            };
+    let client = change_channel_message.client.clone();
 
-            // Add client to connected clients
-            channel.clients.push(change_channel_message.client.clone());
-
-            let client = change_channel_message.client.clone();
-            let channel_name = channel_name;
-
-            let join_msg = join_message(
-                client.username.clone(),
-                client.domain.clone(),
-                channel_name.clone()
-            );
-            // We need to send a sync message, otherwise, the client may receive the channel members
-            // before knowing that they successfully joined the channel
-            send_synchronous_message(client.clone(), join_msg.clone());
-
-            // Say to other users that someone joined the channel
-            let broadcast_message = BroadcastMessage {
-                content: join_msg,
-                channel: channel_name.clone(),
-                sender: change_channel_message.client.clone(),
-                send_to_sender: false
-            };
+    if let Err(numeric) = validate_channel_name(&channel_name) {
+        let reply = Reply::new(numeric, &client.nickname)
+            .param(&channel_name)
+            .trailing(match numeric {
+                Numeric::ErrNoSuchChannel => "No such channel",
+                _ => "Bad Channel Mask"
+            });
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
 
-            match broadcast_tx.send(broadcast_message) {
-                Ok(_) => {},
-                Err(e) => {
-                    println!("Unable to send broadcast channel message: {:?}", e);
-                    return;
-                }
-            };
+    let joined = channels.values().filter(|channel| channel.clients.contains(&client)).count();
+    let already_member = channels.get(&channel_key(&channel_name))
+        .is_some_and(|channel| channel.clients.contains(&client));
+
+    if joined >= config::get().max_channels && !already_member {
+        let reply = Reply::new(Numeric::ErrTooManyChannels, &client.nickname)
+            .param(&channel_name)
+            .trailing("You have joined too many channels");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
+
+    // The channel is created by its first member
+    let created = !channels.contains_key(&channel_key(&channel_name));
+    let channel = channels.entry(channel_key(&channel_name)).or_insert_with(|| {
+        println!("Channel {} created by {}", channel_name, client.nickname);
+        Channel::new(&channel_name)
+    });
+
+    // Joining a channel twice has no effect
+    if channel.clients.contains(&client) {
+        return
+    }
 
-            // Sens the user list and channel description to the client
-            let join_header = join_header(client.username.clone(), channel);
-            send_synchronous_message(client.clone(), join_header);
+    if channel.is_banned(&client) {
+        let reply = Reply::new(Numeric::ErrBannedFromChan, &client.nickname)
+            .param(&channel.name)
+            .trailing("Cannot join channel (+b)");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
 
-            let members_msg = join_members(client.username.clone(), channel);
-            send_synchronous_message(client.clone(), members_msg);
+    // Invitation, key and limit apply to every channel that already existed, even when empty
+    if !created {
+        let key = change_channel_message.body;
+        let invited = channel.invites.contains(&client.id) || channel.is_invite_excepted(&client);
+        let error = if channel.modes.invite_only && !invited {
+            Some((Numeric::ErrInviteOnlyChan, "Cannot join channel (+i)"))
+        } else if channel.modes.key.is_some() && channel.modes.key != key {
+            Some((Numeric::ErrBadChannelKey, "Cannot join channel (+k)"))
+        } else if channel.modes.limit.is_some_and(|limit| channel.clients.len() >= limit) {
+            Some((Numeric::ErrChannelIsFull, "Cannot join channel (+l)"))
+        } else {
+            None
+        };
 
-            let members_end = join_end_members(client.username.clone(), channel);
-            send_synchronous_message(client.clone(), members_end);
+        if let Some((numeric, text)) = error {
+            let reply = Reply::new(numeric, &client.nickname)
+                .param(&channel.name)
+                .trailing(text);
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
         }
-    });
+    }
+
+    // The creator, or the first member of an empty persistent channel once the checks above
+    // passed, becomes its operator, and owner when enabled
+    if channel.clients.is_empty() {
+        let modes = membership_modes().iter()
+            .map(|(mode, _)| *mode)
+            .filter(|mode| *mode == 'q' || *mode == 'o')
+            .collect();
+        channel.privileges.insert(client.id, modes);
+    }
+
+    // Add client to connected clients, the invitation is used
+    channel.clients.push(client.clone());
+    channel.invites.retain(|id| *id != client.id);
+
+    // Use the name given by the creator of the channel
+    let channel_name = channel.name.clone();
+
+    let join_msg = join_message(
+        &client,
+        channel_name.clone()
+    );
+    // We need to send a sync message, otherwise, the client may receive the channel members
+    // before knowing that they successfully joined the channel
+    send_synchronous_message(client.clone(), join_msg.clone());
+
+    // Say to other users that someone joined the channel
+    let broadcast_message = BroadcastMessage {
+        content: join_msg,
+        channel: channel_name.clone(),
+        sender: change_channel_message.client.clone(),
+        send_to_sender: false,
+        kind: BroadcastKind::Server
+    };
+
+    match broadcast_tx.send(broadcast_message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send broadcast channel message: {:?}", e);
+            return;
+        }
+    };
+
+    // Sens the user list and channel topic to the client
+    if !channel.description.is_empty() {
+        let join_header = join_header(client.nickname.clone(), channel);
+        send_synchronous_message(client.clone(), join_header);
+    }
+
+    let members_msg = join_members(&client, channel);
+    send_synchronous_message(client.clone(), members_msg);
+
+    let members_end = join_end_members(client.nickname.clone(), &channel.name);
+    send_synchronous_message(client.clone(), members_end);
 }
 
 fn leave_channel(
@@ -134,12 +465,6 @@ fn leave_channel(
     // User wants to leave the channel
     let sender = change_channel_message.client.clone();
 
-    // Called when a client unregisters or has a connection error
-    if change_channel_message.all_channels == true {
-        unregister_from_all_channels(sender, channels, broadcast_tx);
-        return
-    }
-
     let channel_to_leave = match change_channel_message.channel.clone() {
         Some(channel) => channel,
         _ => {
@@ -155,35 +480,50 @@ fn leave_channel(
         }
     };
 
-    let channel = match channels.get_mut(&*channel_to_leave) {
+    let channel = match channels.get_mut(&channel_key(&channel_to_leave)) {
         Some(channel) => channel,
         _ => {
-            println!("Unable to find given channel name: {:?}", &*channel_to_leave);
+            let reply = Reply::new(Numeric::ErrNoSuchChannel, &sender.nickname)
+                .param(&channel_to_leave)
+                .trailing("No such channel");
+            send_message(PostmanMessage { client: sender, content: reply.to_line() }, postman_tx);
             return
         }
     };
 
+    if !channel.clients.contains(&sender) {
+        let reply = Reply::new(Numeric::ErrNotOnChannel, &sender.nickname)
+            .param(&channel_to_leave)
+            .trailing("You're not on that channel");
+        send_message(PostmanMessage { client: sender, content: reply.to_line() }, postman_tx);
+        return
+    }
+
+    let channel_to_leave = channel.name.clone();
+
     let body = match change_channel_message.body.clone() {
         Some(body) => body,
         _ => String::from("Bye bye")
     };
 
     let content = part_msg(
-        sender.username.clone(),
-        sender.domain.clone(),
-        String::from(channel_to_leave.clone()),
-        String::from(body).replace('\r', "").replace('\n', "")
+        &sender,
+        channel_to_leave.clone(),
+        body.replace(['\r', '\n'], "")
     );
 
-    // Send message to everyone that a user left
-    let msg = BroadcastMessage {
-        content: content.clone(),
-        channel: channel_to_leave.clone(),
-        sender: sender.clone(),
-        send_to_sender: false
-    };
+    // Send message to everyone that a user left, unless the channel is about to be destroyed
+    if channel.clients.len() > 1 {
+        let msg = BroadcastMessage {
+            content: content.clone(),
+            channel: channel_to_leave.clone(),
+            sender: sender.clone(),
+            send_to_sender: false,
+            kind: BroadcastKind::Server
+        };
 
-    send_broadcast_message(msg, broadcast_tx.clone());
+        send_broadcast_message(msg, broadcast_tx.clone());
+    }
 
     // Send to the client that it left
     let postman_message = PostmanMessage {
@@ -194,61 +534,130 @@ fn leave_channel(
     send_message(postman_message, postman_tx);
 
     // Remove client from connected clients
-    channel.clients.retain(|c| c.clone() != change_channel_message.client.clone());
+    channel.remove_member(&sender);
+
+    remove_empty_channels(&mut channels);
 }
 
-// Function called to unregister client from every channel (ie. when the connection breaks)
-fn unregister_from_all_channels(sender: Client, channels: Arc<Mutex<HashMap<String, Channel>>>, broadcast_tx: Sender<BroadcastMessage>) {
-    let mut channels = match channels.lock() {
-        Ok(channels) => channels,
+// Leaves every channel the client is in, one PART at a time
+fn leave_all_channels(
+    change_channel_message: ChannelMessage,
+    broadcast_tx: Sender<BroadcastMessage>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>,
+) {
+    let channel_names: Vec<String> = match channels.lock() {
+        Ok(channels) => channels.values()
+            .filter(|channel| channel.clients.contains(&change_channel_message.client))
+            .map(|channel| channel.name.clone())
+            .collect(),
         Err(e) => {
-            println!("Unable to acquire channel lock: {:?}", e);
+            println!("Unable to acquire channels lock: {:?}", e);
             return
         }
     };
 
-    for channel in channels.values_mut() {
-        // Check if client in channel
-        if !channel.clone().clients.contains(&sender.clone()) {
-            continue
+    for channel_name in channel_names {
+        let msg = ChannelMessage {
+            client: change_channel_message.client.clone(),
+            channel: Some(channel_name),
+            body: change_channel_message.body.clone(),
+            action: ChannelAction::Leave
+        };
+
+        leave_channel(msg, broadcast_tx.clone(), postman_tx.clone(), channels.clone());
+    }
+}
+
+// NAMES for a single channel, or for every visible channel when none is given
+fn send_names(
+    change_channel_message: ChannelMessage,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
         }
+    };
 
-        let content = part_msg(
-            sender.username.clone(),
-            sender.domain.clone(),
-            String::from(channel.clone().name),
-            String::from("User left")
-        );
+    let client = change_channel_message.client;
+    let mut content = String::new();
 
-        let msg = BroadcastMessage {
-            content: content.clone(),
-            channel: channel.clone().name,
-            sender: sender.clone(),
-            send_to_sender: false
-        };
+    match change_channel_message.channel {
+        Some(channel_name) => {
+            // Unknown, empty and secret channels only get the end of the list
+            if let Some(channel) = channels.get(&channel_key(&channel_name)) {
+                if channel.is_visible_to(&client) && !channel.members_visible_to(&client).is_empty() {
+                    content.push_str(&join_members(&client, channel));
+                }
+            }
 
-        // Say to every other client that client disconnected
-        send_broadcast_message(msg, broadcast_tx.clone());
+            content.push_str(&join_end_members(client.nickname.clone(), &channel_name));
+        }
+        None => {
+            let mut listed: Vec<&Channel> = channels.values()
+                .filter(|channel| channel.is_visible_to(&client) && !channel.members_visible_to(&client).is_empty())
+                .collect();
+            listed.sort_by(|a, b| a.name.cmp(&b.name));
 
-        // Remove client for channel's client vector
-        channel.clients.retain(|c| c != &sender.clone());
+            for channel in listed {
+                content.push_str(&join_members(&client, channel));
+            }
+
+            content.push_str(&join_end_members(client.nickname.clone(), "*"));
+        }
     }
+
+    send_message(PostmanMessage { client, content }, postman_tx);
 }
 
-fn init_default_channels(channels: Arc<Mutex<HashMap<String, Channel>>>) {
-    let rust_channel = Channel {
-        name: String::from("#rust"),
-        description: String::from("Un endroit pour discuter du rust"),
-        clients: Vec::new()
+// LIST: a channel is sent if it matches one of the masks, when any, and every other filter
+fn list_channels(
+    change_channel_message: ChannelMessage,
+    filters: Vec<ListFilter>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
     };
 
-    let java_channel = Channel {
-        name: String::from("#java"),
-        description: String::from("Un endroit pour discuter du java"),
-        clients: Vec::new()
-    };
+    let client = change_channel_message.client;
+    let now = unix_timestamp();
+    let (masks, conditions): (Vec<ListFilter>, Vec<ListFilter>) = filters.into_iter()
+        .partition(|filter| matches!(filter, ListFilter::Mask(_)));
 
-    let mut channels = match channels.lock() {
+    let mut listed: Vec<&Channel> = channels.values()
+        .filter(|channel| channel.is_visible_to(&client))
+        .filter(|channel| masks.is_empty() || masks.iter().any(|mask| mask.accepts(channel, now)))
+        .filter(|channel| conditions.iter().all(|condition| condition.accepts(channel, now)))
+        .collect();
+    listed.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut content = list_start(client.nickname.clone());
+    for channel in listed {
+        content.push_str(&list_entry(client.nickname.clone(), channel));
+    }
+    content.push_str(&list_end(client.nickname.clone()));
+
+    send_message(PostmanMessage { client, content }, postman_tx);
+}
+
+// WHO of a channel: members of visible channels, "H" or "G" being followed by their prefix
+fn who_channel(
+    change_channel_message: ChannelMessage,
+    whox: Option<String>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let channels = match channels.lock() {
         Ok(channels) => channels,
         Err(e) => {
             println!("Unable to acquire channels lock: {:?}", e);
@@ -256,35 +665,782 @@ fn init_default_channels(channels: Arc<Mutex<HashMap<String, Channel>>>) {
         }
     };
 
-    channels.insert(String::from("#rust"), rust_channel);
-    channels.insert(String::from("#java"), java_channel);
+    let client = change_channel_message.client;
+    let channel_name = match change_channel_message.channel {
+        Some(channel_name) => channel_name,
+        _ => {
+            println!("Cannot list members without channel name!");
+            return
+        }
+    };
+
+    let mut content = String::new();
+
+    if let Some(channel) = channels.get(&channel_key(&channel_name)) {
+        if channel.is_visible_to(&client) {
+            for member in channel.members_visible_to(&client) {
+                let flags = format!("{}{}", member.presence(), channel.prefix(member));
+                let show_address = client.user_modes().operator || member == &client;
+                content.push_str(&who_reply(&client.nickname, &channel.name, member, &flags, whox.as_deref(), show_address));
+            }
+        }
+    }
+
+    content.push_str(&end_of_who(&client.nickname, &channel_name));
+    send_message(PostmanMessage { client, content }, postman_tx);
 }
 
-fn send_synchronous_message(client: Client, message: String) {
-    let mut writer = BufWriter::new(client.stream);
-    match writer.write(message.as_bytes()) {
-        Ok(_) => {},
+// WHO of a mask: the registration thread lists the matching clients, invisible ones only if
+// they share a channel with the client
+fn who_mask(
+    change_channel_message: ChannelMessage,
+    whox: Option<String>,
+    registration_tx: Sender<RegistrationMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let channels = match channels.lock() {
+        Ok(channels) => channels,
         Err(e) => {
-            println!("Unable to send channel message: {:?}", e);
+            println!("Unable to acquire channels lock: {:?}", e);
             return
         }
     };
 
-    match writer.flush() {
-        Ok(_) => {},
+    let client = change_channel_message.client;
+    let mask = change_channel_message.body.unwrap_or_default();
+
+    let mut peers: Vec<Uuid> = Vec::new();
+    for channel in channels.values().filter(|channel| channel.clients.contains(&client)) {
+        for member in channel.clients.iter() {
+            if !peers.contains(&member.id) {
+                peers.push(member.id);
+            }
+        }
+    }
+
+    let msg = RegistrationMessage {
+        client,
+        action: RegistrationAction::Who { mask, whox, peers }
+    };
+
+    send_registration_message(msg, registration_tx);
+}
+
+// Channels of the target shown in WHOIS, secret and private ones only to their members
+fn whois_channels_of(
+    change_channel_message: ChannelMessage,
+    target: Client,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let channels = match channels.lock() {
+        Ok(channels) => channels,
         Err(e) => {
-            println!("Unable to flush channel message {:?}", e);
+            println!("Unable to acquire channels lock: {:?}", e);
             return
         }
     };
+
+    let client = change_channel_message.client;
+
+    let mut shown: Vec<&Channel> = channels.values()
+        .filter(|channel| channel.clients.contains(&target))
+        .filter(|channel| !(channel.modes.secret || channel.modes.private) || channel.clients.contains(&client))
+        .collect();
+    shown.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let names: Vec<String> = shown.iter()
+        .map(|channel| format!("{}{}", channel.prefix(&target), channel.name))
+        .collect();
+
+    let mut content = String::new();
+    if !names.is_empty() {
+        content.push_str(&whois_channels(&client.nickname, &target.nickname, &names));
+    }
+    content.push_str(&whois_idle(&client.nickname, &target));
+    content.push_str(&end_of_whois(&client.nickname, &target.nickname));
+
+    send_message(PostmanMessage { client, content }, postman_tx);
 }
 
-pub fn send_channel_message(message: ChannelMessage, channel_tx: Sender<ChannelMessage>) {
-    match channel_tx.send(message) {
-        Ok(_) => {},
+// Function called to unregister client from every channel (ie. when the connection breaks)
+fn unregister_from_all_channels(
+    change_channel_message: ChannelMessage,
+    channels: Arc<Mutex<HashMap<String, Channel>>>,
+    postman_tx: Sender<PostmanMessage>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
         Err(e) => {
-            println!("Unable to send channel channel message: {:?}",e);
+            println!("Unable to acquire channel lock: {:?}", e);
             return
         }
-    }
-}
\ No newline at end of file
+    };
+
+    let sender = change_channel_message.client;
+    let reason = change_channel_message.body.unwrap_or_else(|| String::from("Client Quit"));
+
+    let content = quit_msg(
+        &sender,
+        reason.replace(['\r', '\n'], "")
+    );
+
+    // Say once to every client sharing a channel that client disconnected
+    for peer in channel_peers(&channels, &sender) {
+        send_message(PostmanMessage { client: peer, content: content.clone() }, postman_tx.clone());
+    }
+
+    for channel in channels.values_mut() {
+        // Pending invitations are lost with the connection
+        channel.invites.retain(|id| *id != sender.id);
+
+        // Remove client for channel's client vector
+        channel.remove_member(&sender);
+    }
+
+    remove_empty_channels(&mut channels);
+}
+
+// Destroys the channels nobody is in anymore, unless they are persistent
+fn remove_empty_channels(channels: &mut HashMap<String, Channel>) {
+    channels.retain(|_, channel| {
+        let keep = channel.persistent || !channel.clients.is_empty();
+        if !keep {
+            println!("Channel {} destroyed", channel.name);
+        }
+        keep
+    });
+}
+
+pub fn is_channel_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(chantype) => config::get().chantypes.contains(chantype),
+        None => false
+    }
+}
+
+// Channels are stored by lower case name, so that names are compared with the server casemapping
+pub fn channel_key(name: &str) -> String {
+    config::get().casemapping.to_lower(name)
+}
+
+// Channel names start with one of CHANTYPES and cannot contain spaces, commas, colons or ^G
+// (RFC 2812 1.3)
+fn validate_channel_name(name: &str) -> Result<(), Numeric> {
+    let config = config::get();
+
+    if !is_channel_name(name) {
+        return Err(Numeric::ErrNoSuchChannel)
+    }
+
+    if name.len() < 2
+        || name.len() > config.channel_length
+        || name.chars().any(|c| matches!(c, ' ' | ',' | ':' | '\x07' | '\0' | '\r' | '\n')) {
+        return Err(Numeric::ErrBadChanMask)
+    }
+
+    Ok(())
+}
+
+fn change_topic(
+    change_channel_message: ChannelMessage,
+    broadcast_tx: Sender<BroadcastMessage>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
+
+    let client = change_channel_message.client;
+    let channel_name = match change_channel_message.channel {
+        Some(channel_name) => channel_name,
+        _ => {
+            println!("Cannot get topic without channel name!");
+            return
+        }
+    };
+
+    // Secret channels are hidden from non members, as in NAMES, LIST and WHO
+    let channel = match channels.get_mut(&channel_key(&channel_name)) {
+        Some(channel) if channel.is_visible_to(&client) => channel,
+        _ => {
+            let reply = Reply::new(Numeric::ErrNoSuchChannel, &client.nickname)
+                .param(&channel_name)
+                .trailing("No such channel");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+    };
+
+    let topic = match change_channel_message.body {
+        Some(topic) => topic,
+        // Topic query
+        _ => {
+            let content = join_header(client.nickname.clone(), channel);
+            send_message(PostmanMessage { client, content }, postman_tx);
+            return
+        }
+    };
+
+    if !channel.clients.contains(&client) {
+        let reply = Reply::new(Numeric::ErrNotOnChannel, &client.nickname)
+            .param(&channel.name)
+            .trailing("You're not on that channel");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
+
+    if channel.modes.topic_ops_only && !channel.has_privilege(&client, 'h') {
+        let reply = Reply::new(Numeric::ErrChanOPrivsNeeded, &client.nickname)
+            .param(&channel.name)
+            .trailing("You're not channel operator");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
+
+    channel.description = topic.chars().take(config::get().topic_length).collect();
+    channel.topic_setter = client.hostmask();
+    channel.topic_time = unix_timestamp();
+
+    let content = topic_msg(
+        &client,
+        channel.name.clone(),
+        channel.description.clone()
+    );
+
+    // Every member, including the one who changed it, is told about the new topic
+    let msg = BroadcastMessage {
+        content,
+        channel: channel.name.clone(),
+        sender: client,
+        send_to_sender: true,
+        kind: BroadcastKind::Server
+    };
+
+    send_broadcast_message(msg, broadcast_tx);
+}
+
+fn change_mode(
+    change_channel_message: ChannelMessage,
+    args: Vec<String>,
+    broadcast_tx: Sender<BroadcastMessage>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
+
+    let client = change_channel_message.client;
+    let channel_name = match change_channel_message.channel {
+        Some(channel_name) => channel_name,
+        _ => {
+            println!("Cannot change modes without channel name!");
+            return
+        }
+    };
+
+    let channel = match channels.get_mut(&channel_key(&channel_name)) {
+        Some(channel) => channel,
+        _ => {
+            let reply = Reply::new(Numeric::ErrNoSuchChannel, &client.nickname)
+                .param(&channel_name)
+                .trailing("No such channel");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+    };
+
+    // Mode query
+    if args.is_empty() {
+        let show_key = channel.clients.contains(&client);
+        let content = channel_mode_is(client.nickname.clone(), channel, show_key);
+        send_message(PostmanMessage { client, content }, postman_tx);
+        return
+    }
+
+    let prefix_modes: String = membership_modes().iter().map(|(mode, _)| *mode).collect();
+
+    // +k and -k take a key, +l a limit, membership modes a nickname, list modes a hostmask
+    let changes = parse_mode_changes(&args, |mode, adding| {
+        mode == 'k' || (mode == 'l' && adding) || prefix_modes.contains(mode) || CHANNEL_LIST_MODES.contains(mode)
+    });
+    let mut applied = Vec::new();
+    let mut replies = String::new();
+    let mut denied = false;
+
+    for change in changes {
+        // List modes without a mask are queries, anyone can see the lists
+        if CHANNEL_LIST_MODES.contains(change.mode) && change.param.is_none() {
+            let channel_name = channel.name.clone();
+            if let Some(entries) = channel.mask_list(change.mode) {
+                replies.push_str(&mask_list(client.nickname.clone(), &channel_name, change.mode, entries));
+            }
+            continue
+        }
+
+
+        // Members can only give or take modes up to their own, halfops can voice and ban
+        let required = match change.mode {
+            'q' => 'q',
+            'v' | 'b' => 'h',
+            _ => 'o'
+        };
+
+        if !channel.has_privilege(&client, required) {
+            if !denied {
+                let reply = Reply::new(Numeric::ErrChanOPrivsNeeded, &client.nickname)
+                    .param(&channel.name)
+                    .trailing("You're not channel operator");
+                replies.push_str(&reply.to_line());
+                denied = true;
+            }
+            continue
+        }
+
+        match (change.mode, change.adding) {
+            (mode, adding) if prefix_modes.contains(mode) => {
+                let nickname = match &change.param {
+                    Some(nickname) => nickname.clone(),
+                    _ => {
+                        replies.push_str(&need_more_params(&client.nickname, "MODE"));
+                        continue
+                    }
+                };
+
+                let member = match find_member(channel, &nickname) {
+                    Some(member) => member,
+                    _ => {
+                        let reply = Reply::new(Numeric::ErrUserNotInChannel, &client.nickname)
+                            .param(&nickname)
+                            .param(&channel.name)
+                            .trailing("They aren't on that channel");
+                        replies.push_str(&reply.to_line());
+                        continue
+                    }
+                };
+
+                let modes = channel.privileges.entry(member.id).or_default();
+                if modes.contains(mode) == adding {
+                    continue
+                }
+
+                if adding {
+                    modes.push(mode);
+                } else {
+                    modes.retain(|m| m != mode);
+                }
+
+                applied.push(ModeChange { param: Some(member.nickname), ..change });
+            }
+            ('k', true) => {
+                let key = match &change.param {
+                    Some(key) if !key.is_empty() && !key.contains(',') => key.clone(),
+                    _ => {
+                        replies.push_str(&need_more_params(&client.nickname, "MODE"));
+                        continue
+                    }
+                };
+
+                if channel.modes.key.is_some() {
+                    let reply = Reply::new(Numeric::ErrKeySet, &client.nickname)
+                        .param(&channel.name)
+                        .trailing("Channel key already set");
+                    replies.push_str(&reply.to_line());
+                    continue
+                }
+
+                channel.modes.key = Some(key);
+                applied.push(change);
+            }
+            ('k', false) => {
+                if channel.modes.key.take().is_some() {
+                    applied.push(ModeChange { param: Some(String::from("*")), ..change });
+                }
+            }
+            ('l', true) => {
+                let limit = match change.param.as_ref().and_then(|limit| limit.parse::<usize>().ok()) {
+                    Some(limit) if limit > 0 => limit,
+                    _ => {
+                        replies.push_str(&need_more_params(&client.nickname, "MODE"));
+                        continue
+                    }
+                };
+
+                channel.modes.limit = Some(limit);
+                applied.push(ModeChange { param: Some(limit.to_string()), ..change });
+            }
+            ('l', false) => {
+                if channel.modes.limit.take().is_some() {
+                    applied.push(change);
+                }
+            }
+            (mode, adding) if CHANNEL_LIST_MODES.contains(mode) => {
+                let mask = normalize_mask(change.param.as_deref().unwrap_or_default());
+                let set_by = client.hostmask();
+                let casemapping = config::get().casemapping;
+                let entries = match channel.mask_list(mode) {
+                    Some(entries) => entries,
+                    None => continue
+                };
+                let existing = entries.iter().position(|entry| casemapping.equals(&entry.mask, &mask));
+
+                match (adding, existing) {
+                    (true, None) if entries.len() >= config::get().max_list_entries => {
+                        let reply = Reply::new(Numeric::ErrBanListFull, &client.nickname)
+                            .param(&channel.name)
+                            .param(&mode.to_string())
+                            .trailing("Channel list is full");
+                        replies.push_str(&reply.to_line());
+                    }
+                    (true, None) => {
+                        entries.push(MaskEntry { mask: mask.clone(), set_by, set_at: unix_timestamp() });
+                        applied.push(ModeChange { param: Some(mask), ..change });
+                    }
+                    (false, Some(index)) => {
+                        let entry = entries.remove(index);
+                        applied.push(ModeChange { param: Some(entry.mask), ..change });
+                    }
+                    _ => {}
+                }
+            }
+            (mode, adding) if CHANNEL_FLAG_MODES.contains(mode) => {
+                if channel.modes.has_flag(mode) != adding {
+                    channel.modes.set_flag(mode, adding);
+                    applied.push(change);
+                }
+            }
+            (mode, _) => {
+                let reply = Reply::new(Numeric::ErrUnknownMode, &client.nickname)
+                    .param(&mode.to_string())
+                    .trailing(&format!("is unknown mode char to me for {}", channel.name));
+                replies.push_str(&reply.to_line());
+            }
+        }
+    }
+
+    if !replies.is_empty() {
+        send_message(PostmanMessage { client: client.clone(), content: replies }, postman_tx);
+    }
+
+    if applied.is_empty() {
+        return
+    }
+
+    let content = mode_msg(
+        &client,
+        channel.name.clone(),
+        format_mode_changes(&applied)
+    );
+
+    let msg = BroadcastMessage {
+        content,
+        channel: channel.name.clone(),
+        sender: client,
+        send_to_sender: true,
+        kind: BroadcastKind::Server
+    };
+
+    send_broadcast_message(msg, broadcast_tx);
+}
+
+fn kick_members(
+    change_channel_message: ChannelMessage,
+    nicknames: Vec<String>,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
+
+    let client = change_channel_message.client;
+    let channel_name = match change_channel_message.channel {
+        Some(channel_name) => channel_name,
+        _ => {
+            println!("Cannot kick without channel name!");
+            return
+        }
+    };
+
+    let channel = match channels.get_mut(&channel_key(&channel_name)) {
+        Some(channel) => channel,
+        _ => {
+            let reply = Reply::new(Numeric::ErrNoSuchChannel, &client.nickname)
+                .param(&channel_name)
+                .trailing("No such channel");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+    };
+
+    if !channel.clients.contains(&client) {
+        let reply = Reply::new(Numeric::ErrNotOnChannel, &client.nickname)
+            .param(&channel.name)
+            .trailing("You're not on that channel");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
+
+    if !channel.has_privilege(&client, 'h') {
+        let reply = Reply::new(Numeric::ErrChanOPrivsNeeded, &client.nickname)
+            .param(&channel.name)
+            .trailing("You're not channel operator");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return
+    }
+
+    let reason = match change_channel_message.body {
+        Some(reason) if !reason.is_empty() => reason,
+        _ => client.nickname.clone()
+    };
+
+    for nickname in nicknames {
+        let member = match find_member(channel, &nickname) {
+            Some(member) => member,
+            _ => {
+                let reply = Reply::new(Numeric::ErrUserNotInChannel, &client.nickname)
+                    .param(&nickname)
+                    .param(&channel.name)
+                    .trailing("They aren't on that channel");
+                send_message(PostmanMessage { client: client.clone(), content: reply.to_line() }, postman_tx.clone());
+                continue
+            }
+        };
+
+        let content = kick_msg(
+            &client,
+            channel.name.clone(),
+            member.nickname.clone(),
+            reason.clone()
+        );
+
+        // Sent before removing the member so that they are told too
+        for recipient in channel.clients.iter() {
+            let postman_message = PostmanMessage {
+                client: recipient.clone(),
+                content: content.clone()
+            };
+
+            send_message(postman_message, postman_tx.clone());
+        }
+
+        channel.remove_member(&member);
+    }
+
+    remove_empty_channels(&mut channels);
+}
+
+fn invite_client(
+    change_channel_message: ChannelMessage,
+    target: Client,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
+
+    let client = change_channel_message.client;
+    let channel_name = match change_channel_message.channel {
+        Some(channel_name) => channel_name,
+        _ => {
+            println!("Cannot invite without channel name!");
+            return
+        }
+    };
+
+    // Invitations to channels that do not exist are only relayed (RFC 2812 3.2.7)
+    if let Some(channel) = channels.get_mut(&channel_key(&channel_name)) {
+        if !channel.clients.contains(&client) {
+            let reply = Reply::new(Numeric::ErrNotOnChannel, &client.nickname)
+                .param(&channel.name)
+                .trailing("You're not on that channel");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+
+        if channel.clients.contains(&target) {
+            let reply = Reply::new(Numeric::ErrUserOnChannel, &client.nickname)
+                .param(&target.nickname)
+                .param(&channel.name)
+                .trailing("is already on channel");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+
+        if channel.modes.invite_only && !channel.has_privilege(&client, 'h') {
+            let reply = Reply::new(Numeric::ErrChanOPrivsNeeded, &client.nickname)
+                .param(&channel.name)
+                .trailing("You're not channel operator");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+
+        if !channel.invites.contains(&target.id) {
+            channel.invites.push(target.id);
+        }
+    }
+
+    let inviting = Reply::new(Numeric::RplInviting, &client.nickname)
+        .param(&target.nickname)
+        .param(&channel_name);
+    send_message(PostmanMessage { client: client.clone(), content: inviting.to_line() }, postman_tx.clone());
+
+    let content = invite_msg(
+        &client,
+        target.nickname.clone(),
+        channel_name
+    );
+    send_message(PostmanMessage { client: target, content }, postman_tx);
+}
+
+// Looks for a member of the channel, comparing nicknames with the server casemapping
+fn find_member(channel: &Channel, nickname: &str) -> Option<Client> {
+    let casemapping = config::get().casemapping;
+    channel.clients.iter()
+        .find(|c| casemapping.equals(&c.nickname, nickname))
+        .cloned()
+}
+
+// Updates the client in every channel and tells each user sharing a channel with them once
+fn change_nickname(
+    change_channel_message: ChannelMessage,
+    postman_tx: Sender<PostmanMessage>,
+    channels: Arc<Mutex<HashMap<String, Channel>>>
+) {
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
+
+    let client = change_channel_message.client;
+    let content = match change_channel_message.body {
+        Some(content) => content,
+        _ => {
+            println!("Cannot change nickname without NICK message!");
+            return
+        }
+    };
+
+    for channel in channels.values_mut() {
+        for member in channel.clients.iter_mut() {
+            if *member == client {
+                *member = client.clone();
+            }
+        }
+    }
+
+    let mut recipients = channel_peers(&channels, &client);
+    recipients.push(client);
+
+    for recipient in recipients {
+        let postman_message = PostmanMessage {
+            client: recipient,
+            content: content.clone()
+        };
+
+        send_message(postman_message, postman_tx.clone());
+    }
+}
+
+// Clients sharing at least one channel with the given client, each listed once
+fn channel_peers(channels: &HashMap<String, Channel>, client: &Client) -> Vec<Client> {
+    let mut peers: Vec<Client> = Vec::new();
+
+    for channel in channels.values() {
+        if !channel.clients.contains(client) {
+            continue
+        }
+
+        for member in channel.clients.iter() {
+            if member != client && !peers.contains(member) {
+                peers.push(member.clone());
+            }
+        }
+    }
+
+    peers
+}
+
+fn init_default_channels(channels: Arc<Mutex<HashMap<String, Channel>>>) {
+    let config = config::get();
+
+    let mut rust_channel = Channel::new("#rust");
+    rust_channel.description = String::from("Un endroit pour discuter du rust");
+    rust_channel.topic_setter = config.server_name.clone();
+    rust_channel.topic_time = config.created_at;
+    rust_channel.persistent = true;
+
+    let mut java_channel = Channel::new("#java");
+    java_channel.description = String::from("Un endroit pour discuter du java");
+    java_channel.topic_setter = config.server_name.clone();
+    java_channel.topic_time = config.created_at;
+    java_channel.persistent = true;
+
+    let mut channels = match channels.lock() {
+        Ok(channels) => channels,
+        Err(e) => {
+            println!("Unable to acquire channels lock: {:?}", e);
+            return
+        }
+    };
+
+    channels.insert(channel_key(&rust_channel.name), rust_channel);
+    channels.insert(channel_key(&java_channel.name), java_channel);
+}
+
+fn send_synchronous_message(client: Client, message: String) {
+    let mut writer = BufWriter::new(client.stream);
+    match writer.write_all(message.as_bytes()) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send channel message: {:?}", e);
+            return
+        }
+    };
+
+    match writer.flush() {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to flush channel message {:?}", e);
+        }
+    };
+}
+
+pub fn send_channel_message(message: ChannelMessage, channel_tx: Sender<ChannelMessage>) {
+    match channel_tx.send(message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send channel channel message: {:?}",e);
+        }
+    }
+}
+
+// Masks are matched against the displayed and the real hostname of the client
+fn matches_any(entries: &[MaskEntry], client: &Client) -> bool {
+    let casemapping = config::get().casemapping;
+    let hostmasks = [client.hostmask(), client.real_hostmask()];
+
+    entries.iter().any(|entry| hostmasks.iter().any(|hostmask| casemapping.matches(&entry.mask, hostmask)))
+}
@@ -1,21 +1,45 @@
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, BufWriter, Write, ErrorKind};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::time::Duration;
+use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use uuid::Uuid;
 
-use crate::protocol::{pong, priv_msg};
-use crate::channels::{ChannelMessage, send_channel_message};
-use crate::broadcast::{BroadcastMessage, send_broadcast_message};
+use crate::protocol::{auth_notice, ping, pong, closing_link, text_msg, mode_msg, need_more_params, user_prefix, Reply, Numeric};
+use crate::config;
+use crate::clock::unix_timestamp;
+use crate::channels::{ChannelMessage, ChannelAction, send_channel_message, is_channel_name};
+use crate::broadcast::{BroadcastMessage, BroadcastKind, send_broadcast_message};
 use std::thread;
-use crate::registration::{RegistrationMessage, RegistrationAction};
+use crate::registration::{RegistrationMessage, RegistrationAction, send_registration_message, is_valid_nickname};
+use crate::message::{Message, ParseError};
+use crate::resolver::{Resolver, LookupResult, lookup_hostname};
+use crate::cloak::cloak_host;
+use crate::modes::{UserModes, USER_MODES, SERVER_USER_MODES, parse_mode_changes, format_mode_changes};
 
 pub struct Client {
     pub id: Uuid,
     pub stream: TcpStream,
+    pub nickname: String,
+    // Username given with USER, prefixed with "~" as it is not verified with ident
     pub username: String,
+    pub realname: String,
+    // Address the client connects from, and its hostname (the address if not resolved)
+    pub address: IpAddr,
     pub domain: String,
-    pub channel: Option<String>
+    // Hostname shown instead of the real one when the client is cloaked (+x)
+    pub cloak: String,
+    // User modes, shared by every copy
+    pub modes: Arc<Mutex<UserModes>>,
+    pub channel: Option<String>,
+    // When the client registered, and last sent a PRIVMSG or NOTICE, shared by every copy
+    pub signon: u64,
+    pub last_active: Arc<AtomicU64>,
+    // Away message set with AWAY, shared by every copy
+    pub away: Arc<Mutex<Option<String>>>
 }
 
 impl Clone for Client {
@@ -23,9 +47,70 @@ impl Clone for Client {
         Client {
             id: self.id,
             stream: self.stream.try_clone().expect("Unable to clone client's stream"),
+            nickname: self.nickname.clone(),
             username: self.username.clone(),
+            realname: self.realname.clone(),
+            address: self.address,
             domain: self.domain.clone(),
-            channel: self.channel.clone()
+            cloak: self.cloak.clone(),
+            modes: self.modes.clone(),
+            channel: self.channel.clone(),
+            signon: self.signon,
+            last_active: self.last_active.clone(),
+            away: self.away.clone()
+        }
+    }
+}
+
+impl Client {
+    pub fn user_modes(&self) -> UserModes {
+        match self.modes.lock() {
+            Ok(modes) => modes.clone(),
+            Err(e) => {
+                println!("Unable to acquire user modes lock: {:?}", e);
+                UserModes::default()
+            }
+        }
+    }
+
+    // Hostname shown to other users
+    pub fn host(&self) -> String {
+        match self.user_modes().cloaked {
+            true => self.cloak.clone(),
+            false => self.domain.clone()
+        }
+    }
+
+    // "nick!user@host" used as prefix of the messages of the client
+    pub fn hostmask(&self) -> String {
+        user_prefix(&self.nickname, &self.username, &self.host())
+    }
+
+    // Same with the real hostname, channel bans match both
+    pub fn real_hostmask(&self) -> String {
+        user_prefix(&self.nickname, &self.username, &self.domain)
+    }
+
+    // Seconds since the last PRIVMSG or NOTICE
+    pub fn idle_time(&self) -> u64 {
+        unix_timestamp().saturating_sub(self.last_active.load(Ordering::Relaxed))
+    }
+
+    pub fn away_message(&self) -> Option<String> {
+        match self.away.lock() {
+            Ok(away) => away.clone(),
+            Err(e) => {
+                println!("Unable to acquire away lock: {:?}", e);
+                None
+            }
+        }
+    }
+
+    // "H" (here) or "G" (gone) flag of WHO replies
+    pub fn presence(&self) -> &'static str {
+        match self.away_message() {
+            Some(_) => "G",
+            None => "H"
         }
     }
 }
@@ -36,12 +121,24 @@ impl PartialEq for Client {
     }
 }
 
+// Parameters collected with PASS, NICK and USER until the client is registered (RFC 2812 3.1)
+#[derive(Default)]
+struct PendingRegistration {
+    password: Option<String>,
+    nickname: Option<String>,
+    username: Option<String>,
+    realname: Option<String>,
+    // Looked up when the client connects
+    hostname: String
+}
+
 // Handles client messages and dispatches accordingly
 pub fn start_client_thread(
     client: TcpStream,
     broadcast_tx: Sender<BroadcastMessage>,
     registration_tx: Sender<RegistrationMessage>,
-    channel_tx: Sender<ChannelMessage>
+    channel_tx: Sender<ChannelMessage>,
+    resolver: Arc<dyn Resolver>
 ) {
     thread::spawn(move || {
         let stream = match client.try_clone() {
@@ -59,10 +156,55 @@ pub fn start_client_thread(
         // Client can be either registered or not
         let mut current_client: Option<Client> = Option::None;
 
+        // The timeout is shared by every copy of the stream: a peer that stopped reading cannot
+        // block the postman, or the ERROR sent when closing its link, forever
+        set_write_timeout(&client, config::get().ping_timeout);
+
+        // Messages sent meanwhile are read once the hostname is known
+        let hostname = match client.peer_addr() {
+            Ok(peer_addr) => resolve_hostname(&client, peer_addr.ip(), resolver),
+            Err(e) => {
+                println!("Unable to retrieve peer_addr from stream: {:?}", e);
+                return
+            }
+        };
+
+        let mut pending_registration = PendingRegistration { hostname, ..PendingRegistration::default() };
+
+        // The server sends a PING after ping_interval seconds of silence, and closes the
+        // connection if nothing is received ping_timeout seconds later
+        let config = config::get();
+        let mut awaiting_pong = false;
+        set_read_timeout(&client, config.ping_interval);
+
         loop {
             // Read messages
             match reader.read_line(&mut received_message) {
+                // The client closed the connection without QUIT
+                Ok(0) => {
+                    close_link(&client, &current_client, "Remote host closed the connection", registration_tx.clone());
+                    break
+                }
                 Ok(_) => {
+                    if awaiting_pong {
+                        awaiting_pong = false;
+                        set_read_timeout(&client, config.ping_interval);
+                    }
+
+                    let message = match Message::parse(&received_message) {
+                        Ok(message) => message,
+                        // Empty messages are silently ignored (RFC 1459 2.3.1)
+                        Err(ParseError::Empty) => {
+                            received_message = String::new();
+                            continue
+                        }
+                        Err(e) => {
+                            println!("Unable to parse message {:?}: {:?}", received_message, e);
+                            received_message = String::new();
+                            continue
+                        }
+                    };
+
                     let stream = match client.try_clone() {
                         Ok(stream) => stream,
                         Err(e) => {
@@ -72,55 +214,191 @@ pub fn start_client_thread(
                     };
 
                     let connected = dispatch_message(
-                        received_message.clone(),
+                        message,
                         stream,
                         broadcast_tx.clone(),
                         registration_tx.clone(),
                         channel_tx.clone(),
-                        &mut current_client
+                        &mut current_client,
+                        &mut pending_registration
                     );
 
-                    if connected == false {
+                    if !connected {
                         break;
                     }
 
                     received_message = String::new();
                 },
+                // Partially received lines are kept in received_message
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if awaiting_pong {
+                        close_link(&client, &current_client, "Ping timeout", registration_tx.clone());
+                        break
+                    }
+
+                    send_reply(&client, ping());
+                    awaiting_pong = true;
+                    set_read_timeout(&client, config.ping_timeout);
+                }
+                // Lines that are not valid UTF-8 are dropped, the connection is still usable
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    println!("Ignoring invalid message from client: {:?}", e);
+                    received_message = String::new();
+                }
                 Err(e) => {
-                    println!("Unable to read message fro client: {:?}", e);
-                    // TODO: Client disconnected
-                    continue
+                    println!("Unable to read message from client: {:?}", e);
+                    let reason = match e.kind() {
+                        ErrorKind::ConnectionReset => String::from("Connection reset by peer"),
+                        _ => format!("Read error: {}", e)
+                    };
+
+                    close_link(&client, &current_client, &reason, registration_tx.clone());
+                    break
                 }
             }
         }
 
+        // Releases the socket, the thread ends here
         drop(client);
     });
 }
 
+// Hostname shown for the client, its address if it cannot be resolved
+fn resolve_hostname(stream: &TcpStream, address: IpAddr, resolver: Arc<dyn Resolver>) -> String {
+    // A leading ":" would be read as the start of a trailing parameter
+    let fallback = match address.to_string() {
+        ip if ip.starts_with(':') => format!("0{}", ip),
+        ip => ip
+    };
+
+    let config = config::get();
+    if !config.resolve_hostnames {
+        return fallback
+    }
+
+    send_reply(stream, auth_notice("*** Looking up your hostname..."));
+
+    let (notice, hostname) = match lookup_hostname(resolver, address, Duration::from_secs(config.dns_timeout)) {
+        LookupResult::Found(hostname) => ("*** Found your hostname", hostname),
+        LookupResult::NotFound => ("*** Couldn't look up your hostname", fallback),
+        LookupResult::Mismatch => ("*** Your forward and reverse DNS do not match, ignoring hostname", fallback),
+        LookupResult::TimedOut => ("*** Couldn't look up your hostname (timed out)", fallback)
+    };
+
+    send_reply(stream, auth_notice(notice));
+    hostname
+}
+
+fn set_read_timeout(stream: &TcpStream, seconds: u64) {
+    match stream.set_read_timeout(Some(Duration::from_secs(seconds))) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to set read timeout: {:?}", e);
+        }
+    }
+}
+
+fn set_write_timeout(stream: &TcpStream, seconds: u64) {
+    match stream.set_write_timeout(Some(Duration::from_secs(seconds))) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to set write timeout: {:?}", e);
+        }
+    }
+}
+
 fn dispatch_message(
-    msg: String,
+    msg: Message,
     stream: TcpStream,
     broadcast_tx: Sender<BroadcastMessage>,
     registration_tx: Sender<RegistrationMessage>,
     channel_tx: Sender<ChannelMessage>,
-    current_client_mut: &mut Option<Client>
+    current_client_mut: &mut Option<Client>,
+    pending_registration: &mut PendingRegistration
 ) -> bool {
-    println!("Received message: {:?}", msg);
-
-    // IRC Message format (RFC 1459): "COMMAND ARG1 ARG2...ARGN :BODY"
-    let args: Vec<&str> = msg.split_whitespace().collect();
-    let body: Vec<&str> = msg.split(":").collect();
-
-    match args[0] {
-        // Client wants to register
-       "NICK" => {
-            register_client(
-                stream,
-                String::from(args[1]),
-                registration_tx.clone(),
-                current_client_mut
-            );
+    // Passwords given to PASS and OPER are kept out of the logs
+    match &*msg.command {
+        "PASS" | "OPER" => println!("Received message: {} <redacted>", msg.command),
+        _ => println!("Received message: {}", msg)
+    }
+
+    // Only registration commands are accepted until registration is complete
+    if current_client_mut.is_none() && !matches!(&*msg.command, "PASS" | "NICK" | "USER" | "PING" | "PONG" | "QUIT") {
+        let reply = Reply::new(Numeric::ErrNotRegistered, "*")
+            .trailing("You have not registered");
+        send_reply(&stream, reply.to_line());
+        return true
+    }
+
+    match &*msg.command {
+        "PASS" => {
+            if current_client_mut.is_some() {
+                send_reply(&stream, already_registered(current_client_mut));
+                return true
+            }
+
+            match msg.param(0) {
+                Some(password) => pending_registration.password = Some(String::from(password)),
+                _ => send_reply(&stream, need_more_params("*", "PASS"))
+            }
+        }
+        "NICK" => {
+            let nick = match msg.param(0) {
+                Some(nick) => nick,
+                _ => {
+                    let reply = Reply::new(Numeric::ErrNoNicknameGiven, &current_nick(current_client_mut))
+                        .trailing("No nickname given");
+                    send_reply(&stream, reply.to_line());
+                    return true
+                }
+            };
+
+            if let Some(current_client) = current_client_mut {
+                change_nickname(current_client, String::from(nick), registration_tx);
+                return true
+            }
+
+            // Rejected right away, without waiting for USER
+            if !is_valid_nickname(nick) {
+                let reply = Reply::new(Numeric::ErrErroneusNickname, "*")
+                    .param(nick)
+                    .trailing("Erroneous nickname");
+                send_reply(&stream, reply.to_line());
+                return true
+            }
+
+            pending_registration.nickname = Some(String::from(nick));
+            return complete_registration(stream, registration_tx, current_client_mut, pending_registration)
+        }
+        "USER" => {
+            if current_client_mut.is_some() {
+                send_reply(&stream, already_registered(current_client_mut));
+                return true
+            }
+
+            // USER <user> <mode> <unused> <realname>
+            let (username, realname) = match (msg.param(0), msg.param(3)) {
+                (Some(username), Some(realname)) => (username, realname),
+                _ => {
+                    send_reply(&stream, need_more_params("*", "USER"));
+                    return true
+                }
+            };
+
+            // "!" and "@" would make the user@host prefix ambiguous
+            let username: String = username.chars()
+                .filter(|c| !matches!(c, '!' | '@' | '~') && !c.is_control())
+                .take(config::get().user_length)
+                .collect();
+
+            if username.is_empty() {
+                send_reply(&stream, need_more_params("*", "USER"));
+                return true
+            }
+
+            pending_registration.username = Some(username);
+            pending_registration.realname = Some(String::from(realname));
+            return complete_registration(stream, registration_tx, current_client_mut, pending_registration)
         }
         // Client wants to join a channel
         "JOIN" => {
@@ -132,54 +410,99 @@ fn dispatch_message(
                 }
             };
 
-            let msg = ChannelMessage {
-                client: current_client.clone(),
-                channel: Some(String::from(args[1])),
-                body: Option::None,
-                leave: false,
-                all_channels: false
-            };
-
-            match channel_tx.send(msg) {
-                Ok(_) => {},
-                Err(e) => {
-                    println!("Unable to send message to channel channel: {:?}", e);
+            let channels = match msg.param(0) {
+                Some(channels) => channels,
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "JOIN"));
                     return true
                 }
             };
+
+            // "JOIN #a,#b key_a,key_b", keys being given in the same order as the channels
+            let mut keys = msg.param(1).unwrap_or_default().split(',');
+
+            for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+                let key = keys.next().filter(|key| !key.is_empty()).map(String::from);
+
+                // "JOIN 0" leaves every channel
+                let msg = match channel {
+                    "0" => ChannelMessage {
+                        client: current_client.clone(),
+                        channel: None,
+                        body: None,
+                        action: ChannelAction::Leave
+                    },
+                    _ => ChannelMessage {
+                        client: current_client.clone(),
+                        channel: Some(String::from(channel)),
+                        // Channel key
+                        body: key,
+                        action: ChannelAction::Join
+                    }
+                };
+
+                send_channel_message(msg, channel_tx.clone());
+            }
         }
         // Clients wants to check if connectio still alive
         "PING" => {
+            let token = match msg.param(0) {
+                Some(token) if !token.is_empty() => token,
+                _ => {
+                    let reply = Reply::new(Numeric::ErrNoOrigin, &current_nick(current_client_mut))
+                        .trailing("No origin specified");
+                    send_reply(&stream, reply.to_line());
+                    return true
+                }
+            };
+
             // Message is sent without postman, because the message can be received even if client
             // has not registered yet.
-            let stream = match stream.try_clone() {
-                Ok(stream) => stream,
-                Err(e) => {
-                    println!("Unable to clone TcpStream: {:?}", e);
+            send_reply(&stream, pong(String::from(token)));
+        }
+        // Client sends a message to channels or users
+        "PRIVMSG" | "NOTICE" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
                     return true
                 }
             };
 
-            let mut writer = BufWriter::new(stream);
-            match writer.write(pong(String::from(args[1])).as_bytes()) {
-                Ok(_) => {},
-                Err(e) => {
-                    println!("Unable to send back pong message: {:?}", e);
+            send_text_message(&msg, current_client, &stream, broadcast_tx, registration_tx);
+        }
+        // Client wants to leave a channel
+        "PART" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
                     return true
                 }
             };
 
-            match writer.flush() {
-                Ok(_) => {},
-                Err(e) => {
-                    println!("Unable to flush pong message: {:?}", e);
+            let channels = match msg.param(0) {
+                Some(channels) => channels,
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "PART"));
                     return true
                 }
+            };
+
+            for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+                let part = ChannelMessage {
+                    client: current_client.clone(),
+                    channel: Some(String::from(channel)),
+                    body: msg.param(1).map(String::from),
+                    action: ChannelAction::Leave
+                };
+
+                send_channel_message(part, channel_tx.clone());
             }
         }
-        // Client sends a message
-        // TODO: Add support for direct PRIVMSG
-        "PRIVMSG" => {
+        // Client wants to read or change the topic of a channel
+        "TOPIC" => {
             let current_client = match current_client_mut {
                 Some(client) => client,
                 _ => {
@@ -188,25 +511,55 @@ fn dispatch_message(
                 }
             };
 
-            let sender = current_client.clone();
-            let content = priv_msg(
-                sender.username.clone(),
-                sender.domain.clone(),
-                String::from(args[1]),
-                String::from(body[1]).replace('\r', "").replace('\n', "")
-            );
+            let channel = match msg.param(0) {
+                Some(channel) => channel,
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "TOPIC"));
+                    return true
+                }
+            };
+
+            let msg = ChannelMessage {
+                client: current_client.clone(),
+                channel: Some(String::from(channel)),
+                body: msg.param(1).map(String::from),
+                action: ChannelAction::Topic
+            };
 
-            let msg = BroadcastMessage {
-                content,
-                channel: String::from(args[1]),
-                sender,
-                send_to_sender: false
+            send_channel_message(msg, channel_tx);
+        }
+        // Client wants the members of channels
+        "NAMES" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
             };
 
-            send_broadcast_message(msg, broadcast_tx.clone());
+            // Without parameter, every visible channel is listed
+            let channels: Vec<Option<String>> = match msg.param(0) {
+                Some(channels) => channels.split(',')
+                    .filter(|channel| !channel.is_empty())
+                    .map(|channel| Some(String::from(channel)))
+                    .collect(),
+                _ => vec![None]
+            };
+
+            for channel in channels {
+                let msg = ChannelMessage {
+                    client: current_client.clone(),
+                    channel,
+                    body: None,
+                    action: ChannelAction::Names
+                };
+
+                send_channel_message(msg, channel_tx.clone());
+            }
         }
-        // Client wants to leave a channel
-        "PART" => {
+        // Client wants to discover channels
+        "LIST" => {
             let current_client = match current_client_mut {
                 Some(client) => client,
                 _ => {
@@ -215,23 +568,93 @@ fn dispatch_message(
                 }
             };
 
-            let body = Option::Some(
-                String::from(body[1]).
-                    replace('\n', "").
-                    replace('\r', "")
-            );
+            // Channel names, masks and ELIST filters, such as "LIST >5,#rust*"
+            let filters = msg.param(0).unwrap_or_default()
+                .split(',')
+                .filter(|filter| !filter.is_empty())
+                .map(String::from)
+                .collect();
 
             let msg = ChannelMessage {
                 client: current_client.clone(),
-                channel: Some(String::from(args[1])),
-                body,
-                leave: true,
-                all_channels: false
+                channel: None,
+                body: None,
+                action: ChannelAction::List(filters)
             };
 
-            send_channel_message(msg, channel_tx.clone());
+            send_channel_message(msg, channel_tx);
         }
-        "QUIT" => {
+        // Client wants to read or change modes
+        "MODE" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let target = match msg.param(0) {
+                Some(target) => target,
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "MODE"));
+                    return true
+                }
+            };
+
+            let args: Vec<String> = (1..msg.param_count())
+                .filter_map(|index| msg.param(index))
+                .map(String::from)
+                .collect();
+
+            if !is_channel_name(target) {
+                change_user_modes(&stream, current_client, target, &args);
+                return true
+            }
+
+            let msg = ChannelMessage {
+                client: current_client.clone(),
+                channel: Some(String::from(target)),
+                body: None,
+                action: ChannelAction::Mode(args)
+            };
+
+            send_channel_message(msg, channel_tx);
+        }
+        // Channel operator wants to remove members from a channel
+        "KICK" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let (channel, nicknames) = match (msg.param(0), msg.param(1)) {
+                (Some(channel), Some(nicknames)) => (channel, nicknames),
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "KICK"));
+                    return true
+                }
+            };
+
+            let nicknames = nicknames.split(',')
+                .filter(|nickname| !nickname.is_empty())
+                .map(String::from)
+                .collect();
+
+            let msg = ChannelMessage {
+                client: current_client.clone(),
+                channel: Some(String::from(channel)),
+                body: msg.param(2).map(String::from),
+                action: ChannelAction::Kick(nicknames)
+            };
+
+            send_channel_message(msg, channel_tx);
+        }
+        // Client wants to invite someone to a channel
+        "INVITE" => {
             let current_client = match current_client_mut {
                 Some(client) => client,
                 _ => {
@@ -239,25 +662,361 @@ fn dispatch_message(
                     return true
                 }
             };
-            unregister_client(current_client.clone(), registration_tx.clone());
 
+            let (target, channel) = match (msg.param(0), msg.param(1)) {
+                (Some(target), Some(channel)) => (target, channel),
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "INVITE"));
+                    return true
+                }
+            };
+
+            let registration_message = RegistrationMessage {
+                client: current_client.clone(),
+                action: RegistrationAction::Invite {
+                    target: String::from(target),
+                    channel: String::from(channel)
+                }
+            };
+
+            match registration_tx.send(registration_message) {
+                Ok(_) => {},
+                Err(e) => {
+                    println!("Unable to send invitation to registration channel: {:?}", e);
+                }
+            }
+        }
+        // Client wants to list users, by channel or by mask
+        "WHO" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let mask = String::from(msg.param(0).unwrap_or_default());
+            // WHOX: "WHO <mask> %<fields>[,<token>]"
+            let whox = msg.param(1).filter(|fields| fields.starts_with('%')).map(String::from);
+
+            // Masks go through the channels thread, which knows who shares a channel with the
+            // client, before reaching the registration thread
+            let msg = match is_channel_name(&mask) {
+                true => ChannelMessage {
+                    client: current_client.clone(),
+                    channel: Some(mask),
+                    body: None,
+                    action: ChannelAction::Who(whox)
+                },
+                false => ChannelMessage {
+                    client: current_client.clone(),
+                    channel: None,
+                    body: Some(mask),
+                    action: ChannelAction::WhoMask(whox)
+                }
+            };
+
+            send_channel_message(msg, channel_tx);
+        }
+        // Client wants information about users
+        "WHOIS" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            // "WHOIS [server] nicknames", the server being this one
+            let targets = match msg.param_count() {
+                0 => {
+                    let reply = Reply::new(Numeric::ErrNoNicknameGiven, &current_client.nickname)
+                        .trailing("No nickname given");
+                    send_reply(&stream, reply.to_line());
+                    return true
+                }
+                1 => msg.param(0),
+                _ => msg.param(1)
+            };
+
+            for target in targets.unwrap_or_default().split(',').filter(|target| !target.is_empty()) {
+                let msg = RegistrationMessage {
+                    client: current_client.clone(),
+                    action: RegistrationAction::Whois(String::from(target))
+                };
+
+                send_registration_message(msg, registration_tx.clone());
+            }
+        }
+        // Client wants information about users that left
+        "WHOWAS" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let targets = match msg.param(0) {
+                Some(targets) => targets,
+                _ => {
+                    let reply = Reply::new(Numeric::ErrNoNicknameGiven, &current_client.nickname)
+                        .trailing("No nickname given");
+                    send_reply(&stream, reply.to_line());
+                    return true
+                }
+            };
+
+            // A count of zero or less means every entry
+            let count = msg.param(1)
+                .and_then(|count| count.parse::<usize>().ok())
+                .filter(|count| *count > 0);
+
+            for target in targets.split(',').filter(|target| !target.is_empty()) {
+                let msg = RegistrationMessage {
+                    client: current_client.clone(),
+                    action: RegistrationAction::Whowas { target: String::from(target), count }
+                };
+
+                send_registration_message(msg, registration_tx.clone());
+            }
+        }
+        // Client is leaving its desk, or coming back without a message
+        "AWAY" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let message: Option<String> = msg.param(0)
+                .filter(|message| !message.is_empty())
+                .map(|message| message.chars().take(config::get().away_length).collect());
+
+            let reply = match &message {
+                Some(_) => Reply::new(Numeric::RplNowAway, &current_client.nickname)
+                    .trailing("You have been marked as being away"),
+                None => Reply::new(Numeric::RplUnAway, &current_client.nickname)
+                    .trailing("You are no longer marked as being away")
+            };
+
+            match current_client.away.lock() {
+                Ok(mut away) => *away = message,
+                Err(e) => {
+                    println!("Unable to acquire away lock: {:?}", e);
+                    return true
+                }
+            }
+
+            send_reply(&stream, reply.to_line());
+        }
+        // Client wants to become an IRC operator
+        "OPER" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let (name, password) = match (msg.param(0), msg.param(1)) {
+                (Some(name), Some(password)) => (name, password),
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "OPER"));
+                    return true
+                }
+            };
+
+            let operators = &config::get().operators;
+            if operators.is_empty() {
+                let reply = Reply::new(Numeric::ErrNoOperHost, &current_client.nickname)
+                    .trailing("No O-lines for your host");
+                send_reply(&stream, reply.to_line());
+                return true
+            }
+
+            if !operators.iter().any(|(n, p)| n == name && p == password) {
+                let reply = Reply::new(Numeric::ErrPasswdMismatch, &current_client.nickname)
+                    .trailing("Password incorrect");
+                send_reply(&stream, reply.to_line());
+                return true
+            }
+
+            match current_client.modes.lock() {
+                Ok(mut modes) => modes.operator = true,
+                Err(e) => {
+                    println!("Unable to acquire modes lock: {:?}", e);
+                    return true
+                }
+            }
+
+            let reply = Reply::new(Numeric::RplYoureOper, &current_client.nickname)
+                .trailing("You are now an IRC operator");
+            let changes = vec![String::from("+o")];
+            send_reply(&stream, reply.to_line() + &mode_msg(current_client, current_client.nickname.clone(), changes));
+        }
+        // Operator sends a message to every client with +w
+        "WALLOPS" => {
+            let current_client = match current_client_mut {
+                Some(client) => client,
+                _ => {
+                    println!("Client not registered! Ignoring message...");
+                    return true
+                }
+            };
+
+            let content = match msg.param(0) {
+                Some(content) if !content.is_empty() => String::from(content),
+                _ => {
+                    send_reply(&stream, need_more_params(&current_client.nickname, "WALLOPS"));
+                    return true
+                }
+            };
+
+            if !current_client.user_modes().operator {
+                let reply = Reply::new(Numeric::ErrNoPrivileges, &current_client.nickname)
+                    .trailing("Permission Denied- You're not an IRC operator");
+                send_reply(&stream, reply.to_line());
+                return true
+            }
+
+            let msg = RegistrationMessage {
+                client: current_client.clone(),
+                action: RegistrationAction::Wallops(content)
+            };
+
+            send_registration_message(msg, registration_tx);
+        }
+        // Answer to the keepalive PING, any received message already counts as activity
+        "PONG" => {}
+        // Client leaves the server, its channel peers are told why
+        "QUIT" => {
+            let reason = match msg.param(0) {
+                Some(reason) if !reason.is_empty() => format!("Quit: {}", reason),
+                _ => String::from("Client Quit")
+            };
+
+            close_link(&stream, current_client_mut, &reason, registration_tx);
             return false
         }
-        _ => {
-            println!("Command {} not found", args[0]);
+        command => {
+            let reply = Reply::new(Numeric::ErrUnknownCommand, &current_nick(current_client_mut))
+                .param(command)
+                .trailing("Unknown command");
+            send_reply(&stream, reply.to_line());
         }
     }
 
-    return true
+    true
+}
+
+// Shows or changes the modes of the client itself. Modes set by the server, such as +o given by
+// OPER, can only be removed.
+fn change_user_modes(stream: &TcpStream, client: &Client, target: &str, args: &[String]) {
+    if !config::get().casemapping.equals(target, &client.nickname) {
+        let reply = Reply::new(Numeric::ErrUsersDontMatch, &client.nickname)
+            .trailing("Cant change mode for other users");
+        send_reply(stream, reply.to_line());
+        return
+    }
+
+    if args.is_empty() {
+        let reply = Reply::new(Numeric::RplUModeIs, &client.nickname)
+            .param(&client.user_modes().to_flags());
+        send_reply(stream, reply.to_line());
+        return
+    }
 
+    let mut applied = Vec::new();
+    let mut unknown = false;
+    {
+        let mut modes = match client.modes.lock() {
+            Ok(modes) => modes,
+            Err(e) => {
+                println!("Unable to acquire modes lock: {:?}", e);
+                return
+            }
+        };
+
+        for change in parse_mode_changes(args, |_, _| false) {
+            if !USER_MODES.contains(change.mode) {
+                unknown = true;
+                continue
+            }
+
+            // Setting server modes is silently ignored, as is setting a mode already set
+            if change.adding && SERVER_USER_MODES.contains(change.mode) {
+                continue
+            }
+
+            if modes.has_flag(change.mode) != change.adding {
+                modes.set_flag(change.mode, change.adding);
+                applied.push(change);
+            }
+        }
+    }
+
+    let mut content = String::new();
+    if unknown {
+        let reply = Reply::new(Numeric::ErrUModeUnknownFlag, &client.nickname)
+            .trailing("Unknown MODE flag");
+        content.push_str(&reply.to_line());
+    }
+
+    if !applied.is_empty() {
+        content.push_str(&mode_msg(client, client.nickname.clone(), format_mode_changes(&applied)));
+    }
+
+    if applied.iter().any(|change| change.mode == 'x') {
+        let reply = Reply::new(Numeric::RplHostHidden, &client.nickname)
+            .param(&client.host())
+            .trailing("is now your displayed host");
+        content.push_str(&reply.to_line());
+    }
+
+    if !content.is_empty() {
+        send_reply(stream, content);
+    }
 }
 
-fn register_client(stream: TcpStream, username: String, registration_tx: Sender<RegistrationMessage>, current_client: &mut Option<Client>) {
-    let local_addr = match stream.local_addr() {
-        Ok(local_addr) => local_addr,
+// Registers the client once both NICK and USER were received. Returns false if the connection
+// must be closed.
+fn complete_registration(
+    stream: TcpStream,
+    registration_tx: Sender<RegistrationMessage>,
+    current_client: &mut Option<Client>,
+    pending_registration: &PendingRegistration
+) -> bool {
+    let (nickname, username, realname) = match pending_registration {
+        PendingRegistration {
+            nickname: Some(nickname),
+            username: Some(username),
+            realname: Some(realname),
+            ..
+        } => (nickname, username, realname),
+        _ => return true
+    };
+
+    if let Some(password) = &config::get().password {
+        if pending_registration.password.as_ref() != Some(password) {
+            let reply = Reply::new(Numeric::ErrPasswdMismatch, nickname)
+                .trailing("Password incorrect");
+            send_reply(&stream, reply.to_line());
+            return false
+        }
+    }
+
+    let peer_addr = match stream.peer_addr() {
+        Ok(peer_addr) => peer_addr,
         Err(e) => {
-            println!("Unable to retrieve local_addr from stream: {:?}", e);
-            return
+            println!("Unable to retrieve peer_addr from stream: {:?}", e);
+            return true
         }
     };
 
@@ -265,45 +1024,229 @@ fn register_client(stream: TcpStream, username: String, registration_tx: Sender<
         Ok(stream) => stream,
         Err(e) => {
             println!("Unable to clone stream: {:?}", e);
-            return
+            return true
         }
     };
 
     let client = Client{
         id: Uuid::new_v4(),
         stream,
-        username: username.clone(),
-        domain: local_addr.ip().to_string(),
-        channel: None
+        nickname: nickname.clone(),
+        username: format!("~{}", username),
+        realname: realname.clone(),
+        address: peer_addr.ip(),
+        domain: pending_registration.hostname.clone(),
+        cloak: cloak_host(&pending_registration.hostname, peer_addr.ip()),
+        modes: Arc::new(Mutex::new(UserModes { cloaked: config::get().cloak_by_default, ..UserModes::default() })),
+        channel: None,
+        signon: unix_timestamp(),
+        last_active: Arc::new(AtomicU64::new(unix_timestamp())),
+        away: Arc::new(Mutex::new(None))
     };
 
-    *current_client = Option::Some(client.clone());
+    // The registration thread tells whether the nickname has been accepted
+    let (result_tx, result_rx) = mpsc::channel();
 
     let registration_message = RegistrationMessage {
-        client,
-        action: RegistrationAction::REGISTER
+        client: client.clone(),
+        action: RegistrationAction::Register(result_tx)
     };
 
     match registration_tx.send(registration_message) {
         Ok(_) => {},
         Err(e) => {
             println!("Unable to send registration message to channel: {:?}", e);
-            return;
+            return true
         }
     };
+
+    match result_rx.recv() {
+        Ok(true) => *current_client = Option::Some(client),
+        Ok(false) => {},
+        Err(e) => {
+            println!("Unable to receive registration result: {:?}", e);
+        }
+    }
+
+    true
 }
 
-fn unregister_client(client: Client, registration_tx: Sender<RegistrationMessage>){
+// Delivers a PRIVMSG or NOTICE to each of its comma separated targets
+fn send_text_message(
+    msg: &Message,
+    sender: &Client,
+    stream: &TcpStream,
+    broadcast_tx: Sender<BroadcastMessage>,
+    registration_tx: Sender<RegistrationMessage>
+) {
+    let notice = msg.command == "NOTICE";
+
+    let (targets, text) = match (msg.param(0), msg.param(1)) {
+        (Some(targets), Some(text)) if !text.is_empty() => (targets, text),
+        (Some(_), _) => {
+            if !notice {
+                let reply = Reply::new(Numeric::ErrNoTextToSend, &sender.nickname)
+                    .trailing("No text to send");
+                send_reply(stream, reply.to_line());
+            }
+            return
+        }
+        _ => {
+            if !notice {
+                let reply = Reply::new(Numeric::ErrNoRecipient, &sender.nickname)
+                    .trailing(&format!("No recipient given ({})", msg.command));
+                send_reply(stream, reply.to_line());
+            }
+            return
+        }
+    };
+
+    sender.last_active.store(unix_timestamp(), Ordering::Relaxed);
+
+    let max_targets = config::get().max_targets;
+
+    for (index, target) in targets.split(',').filter(|t| !t.is_empty()).enumerate() {
+        if index >= max_targets {
+            if !notice {
+                let reply = Reply::new(Numeric::ErrTooManyTargets, &sender.nickname)
+                    .param(target)
+                    .trailing(&format!("Too many recipients. Only {} allowed", max_targets));
+                send_reply(stream, reply.to_line());
+            }
+            continue
+        }
+
+        let content = text_msg(
+            msg.command.clone(),
+            sender,
+            String::from(target),
+            String::from(text)
+        );
+
+        if is_channel_name(target) {
+            let msg = BroadcastMessage {
+                content,
+                channel: String::from(target),
+                sender: sender.clone(),
+                send_to_sender: false,
+                kind: if notice { BroadcastKind::Notice } else { BroadcastKind::Privmsg }
+            };
+
+            send_broadcast_message(msg, broadcast_tx.clone());
+            continue
+        }
+
+        // The registration thread knows every connected nickname
+        let registration_message = RegistrationMessage {
+            client: sender.clone(),
+            action: RegistrationAction::DirectMessage {
+                target: String::from(target),
+                content,
+                notice
+            }
+        };
+
+        match registration_tx.send(registration_message) {
+            Ok(_) => {},
+            Err(e) => {
+                println!("Unable to send direct message to registration channel: {:?}", e);
+            }
+        }
+    }
+}
+
+// Asks the registration thread for a new nickname, and keeps it if accepted
+fn change_nickname(current_client: &mut Client, nickname: String, registration_tx: Sender<RegistrationMessage>) {
+    let (result_tx, result_rx) = mpsc::channel();
+
+    let registration_message = RegistrationMessage {
+        client: current_client.clone(),
+        action: RegistrationAction::ChangeNickname(nickname.clone(), result_tx)
+    };
+
+    match registration_tx.send(registration_message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send nickname change to registration channel: {:?}", e);
+            return
+        }
+    };
+
+    match result_rx.recv() {
+        Ok(true) => current_client.nickname = nickname,
+        Ok(false) => {},
+        Err(e) => {
+            println!("Unable to receive nickname change result: {:?}", e);
+        }
+    }
+}
+
+fn unregister_client(client: Client, reason: &str, registration_tx: Sender<RegistrationMessage>){
     let unregister_message = RegistrationMessage {
         client: client.clone(),
-        action: RegistrationAction::LEAVE
+        action: RegistrationAction::Leave(String::from(reason))
     };
 
     match registration_tx.send(unregister_message) {
         Ok(_) => {},
         Err(e) => {
             println!("Unable to send unregistration message to channel: {:?}", e);
+        }
+    }
+}
+
+// Tells the client why its connection is closed, unregisters it and closes the socket
+fn close_link(
+    stream: &TcpStream,
+    current_client: &Option<Client>,
+    reason: &str,
+    registration_tx: Sender<RegistrationMessage>
+) {
+    send_reply(stream, closing_link(reason));
+
+    if let Some(client) = current_client {
+        unregister_client(client.clone(), reason, registration_tx);
+    }
+
+    // Copies of the stream are held by other threads, shut it down for all of them
+    match stream.shutdown(Shutdown::Both) {
+        Ok(_) => {},
+        // The peer is already gone
+        Err(e) if e.kind() == ErrorKind::NotConnected => {},
+        Err(e) => {
+            println!("Unable to shut down stream: {:?}", e);
+        }
+    }
+}
+
+fn current_nick(current_client: &Option<Client>) -> String {
+    match current_client {
+        Some(client) => client.nickname.clone(),
+        _ => String::from("*")
+    }
+}
+
+fn already_registered(current_client: &Option<Client>) -> String {
+    Reply::new(Numeric::ErrAlreadyRegistred, &current_nick(current_client))
+        .trailing("Unauthorized command (already registered)")
+        .to_line()
+}
+
+// Writes a reply directly on the client's stream, which works even if it is not registered yet
+fn send_reply(stream: &TcpStream, content: String) {
+    let mut writer = BufWriter::new(stream);
+    match writer.write_all(content.as_bytes()) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send reply: {:?}", e);
             return
         }
+    };
+
+    match writer.flush() {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to flush reply: {:?}", e);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+
+use hmac_sha256::HMAC;
+
+use crate::config;
+
+// Replaces a host with keyed hashes, keeping its structure so that bans on a network or a
+// domain still work:
+// - 192.0.2.10 becomes "A1B2C3D4.E5F6A7B8.C9D0E1F2.IP", hashing the address, its /24 and its /16
+// - 2001:db8::1 becomes "A1B2C3D4:E5F6A7B8:C9D0E1F2:IP", hashing the address, its /64 and its /32
+// - host.example.com becomes "irc-A1B2C3D4.example.com"
+pub fn cloak_host(hostname: &str, address: IpAddr) -> String {
+    // The hostname is the address when it has not been resolved
+    if hostname.parse::<IpAddr>().is_ok() {
+        return cloak_address(address)
+    }
+
+    let config = config::get();
+    match hostname.split_once('.') {
+        Some((_, domain)) => format!("{}-{}.{}", config.cloak_prefix, keyed_hash(hostname), domain),
+        None => format!("{}-{}", config.cloak_prefix, keyed_hash(hostname))
+    }
+}
+
+fn cloak_address(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+            let network_24 = format!("{}.{}.{}", octets[0], octets[1], octets[2]);
+            let network_16 = format!("{}.{}", octets[0], octets[1]);
+
+            format!(
+                "{}.{}.{}.IP",
+                keyed_hash(&address.to_string()),
+                keyed_hash(&network_24),
+                keyed_hash(&network_16)
+            )
+        }
+        IpAddr::V6(address) => {
+            let segments = address.segments();
+            let network_64 = format!("{:x}:{:x}:{:x}:{:x}", segments[0], segments[1], segments[2], segments[3]);
+            let network_32 = format!("{:x}:{:x}", segments[0], segments[1]);
+
+            format!(
+                "{}:{}:{}:IP",
+                keyed_hash(&address.to_string()),
+                keyed_hash(&network_64),
+                keyed_hash(&network_32)
+            )
+        }
+    }
+}
+
+// First 32 bits of the HMAC-SHA256 of the input, in hexadecimal
+fn keyed_hash(input: &str) -> String {
+    let mac = HMAC::mac(input.as_bytes(), config::get().cloak_key.as_bytes());
+    mac[..4].iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_hash(segment: &str) -> bool {
+        segment.len() == 8 && segment.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c))
+    }
+
+    #[test]
+    fn cloaks_ipv4_addresses() {
+        let address: IpAddr = "192.0.2.10".parse().unwrap();
+        let cloak = cloak_host("192.0.2.10", address);
+        let segments: Vec<&str> = cloak.split('.').collect();
+
+        assert_eq!(segments.len(), 4);
+        assert!(segments[..3].iter().all(|segment| is_hash(segment)));
+        assert_eq!(segments[3], "IP");
+        assert!(!cloak.contains("192"));
+    }
+
+    #[test]
+    fn cloaks_ipv6_addresses() {
+        let address: IpAddr = "2001:db8::1".parse().unwrap();
+        let cloak = cloak_host("2001:db8::1", address);
+        let segments: Vec<&str> = cloak.split(':').collect();
+
+        assert_eq!(segments.len(), 4);
+        assert!(segments[..3].iter().all(|segment| is_hash(segment)));
+        assert_eq!(segments[3], "IP");
+    }
+
+    #[test]
+    fn keeps_domain_of_hostnames() {
+        let address: IpAddr = "192.0.2.10".parse().unwrap();
+        let cloak = cloak_host("client.example.com", address);
+        let prefix = format!("{}-", config::get().cloak_prefix);
+
+        let (first, domain) = cloak.split_once('.').unwrap();
+        assert_eq!(domain, "example.com");
+        assert!(first.starts_with(&prefix));
+        assert!(is_hash(&first[prefix.len()..]));
+        assert!(!cloak.contains("client"));
+    }
+
+    #[test]
+    fn shares_network_segments_within_a_24() {
+        let first = cloak_host("192.0.2.10", "192.0.2.10".parse().unwrap());
+        let second = cloak_host("192.0.2.200", "192.0.2.200".parse().unwrap());
+        let other_network = cloak_host("192.0.3.10", "192.0.3.10".parse().unwrap());
+
+        let first: Vec<&str> = first.split('.').collect();
+        let second: Vec<&str> = second.split('.').collect();
+        let other_network: Vec<&str> = other_network.split('.').collect();
+
+        assert_ne!(first[0], second[0]);
+        assert_eq!(first[1..], second[1..]);
+        // Another /24 of the same /16 only shares the last hash
+        assert_ne!(first[1], other_network[1]);
+        assert_eq!(first[2..], other_network[2..]);
+    }
+
+    #[test]
+    fn cloaks_are_stable() {
+        let address: IpAddr = "192.0.2.10".parse().unwrap();
+        assert_eq!(cloak_host("192.0.2.10", address), cloak_host("192.0.2.10", address));
+    }
+}
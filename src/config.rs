@@ -0,0 +1,106 @@
+use std::env;
+use std::sync::OnceLock;
+
+use crate::casemapping::CaseMapping;
+use crate::clock::unix_timestamp;
+use uuid::Uuid;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Server settings, read once from the environment
+pub struct Config {
+    pub server_name: String,
+    // Description shown in WHOIS and WHOWAS
+    pub server_info: String,
+    // Password expected in PASS before registration, if any
+    pub password: Option<String>,
+    pub created_at: u64,
+    // Rules used to compare nicknames
+    pub casemapping: CaseMapping,
+    pub nick_length: usize,
+    // Maximum length of usernames, without the "~" prefix
+    pub user_length: usize,
+    // Characters a channel name can start with
+    pub chantypes: String,
+    pub channel_length: usize,
+    // Maximum number of targets of a PRIVMSG or NOTICE
+    pub max_targets: usize,
+    pub topic_length: usize,
+    // Modes set on newly created channels
+    pub default_channel_modes: String,
+    // Membership modes among "qohv" (owner, operator, halfop, voice)
+    pub prefix_modes: String,
+    // Maximum number of entries of each ban, exception and invite exception list
+    pub max_list_entries: usize,
+    // Maximum number of channels a client can be in
+    pub max_channels: usize,
+    // Number of disconnected or renamed nicknames remembered for WHOWAS
+    pub whowas_length: usize,
+    pub away_length: usize,
+    // Seconds of silence before the server sends a PING, and seconds left to answer it
+    pub ping_interval: u64,
+    pub ping_timeout: u64,
+    // Whether hostnames of clients are looked up, and how many seconds to wait for them
+    pub resolve_hostnames: bool,
+    pub dns_timeout: u64,
+    // Secret of the keyed hashes of cloaked hostnames, and whether clients are cloaked (+x)
+    // when they connect
+    pub cloak_key: String,
+    pub cloak_by_default: bool,
+    // Start of the cloaks of resolved hostnames, such as "irc-1A2B3C4D.example.com"
+    pub cloak_prefix: String,
+    // Names and passwords accepted by OPER
+    pub operators: Vec<(String, String)>
+}
+
+impl Config {
+    fn from_env() -> Config {
+        Config {
+            server_name: env::var("IRC_SERVER_NAME").unwrap_or_else(|_| String::from("guyot-gondange.fr")),
+            server_info: env::var("IRC_SERVER_INFO").unwrap_or_else(|_| String::from("Serveur IRC en Rust")),
+            password: env::var("IRC_PASSWORD").ok(),
+            created_at: unix_timestamp(),
+            casemapping: env::var("IRC_CASEMAPPING").ok()
+                .and_then(|name| CaseMapping::from_name(&name))
+                .unwrap_or(CaseMapping::Rfc1459),
+            nick_length: env_number("IRC_NICKLEN", 30),
+            user_length: env_number("IRC_USERLEN", 10),
+            chantypes: env::var("IRC_CHANTYPES").unwrap_or_else(|_| String::from("#&")),
+            channel_length: env_number("IRC_CHANNELLEN", 50),
+            max_targets: env_number("IRC_TARGMAX", 4),
+            topic_length: env_number("IRC_TOPICLEN", 390),
+            default_channel_modes: env::var("IRC_DEFAULT_CHANMODES").unwrap_or_else(|_| String::from("nt")),
+            // Operators are always available, they are given to channel creators
+            prefix_modes: env::var("IRC_PREFIX_MODES").unwrap_or_else(|_| String::from("ov")) + "o",
+            max_list_entries: env_number("IRC_MAXLIST", 50),
+            max_channels: env_number("IRC_CHANLIMIT", 20),
+            whowas_length: env_number("IRC_WHOWAS", 100),
+            away_length: env_number("IRC_AWAYLEN", 200),
+            ping_interval: env_number("IRC_PING_INTERVAL", 120).max(1) as u64,
+            ping_timeout: env_number("IRC_PING_TIMEOUT", 60).max(1) as u64,
+            resolve_hostnames: env::var("IRC_RESOLVE_HOSTNAMES").map(|value| value != "false").unwrap_or(true),
+            dns_timeout: env_number("IRC_DNS_TIMEOUT", 5).max(1) as u64,
+            // Without a key, cloaks change every time the server restarts
+            cloak_key: env::var("IRC_CLOAK_KEY").unwrap_or_else(|_| Uuid::new_v4().to_string()),
+            cloak_by_default: env::var("IRC_CLOAK").map(|value| value != "false").unwrap_or(true),
+            cloak_prefix: env::var("IRC_CLOAK_PREFIX").unwrap_or_else(|_| String::from("irc")),
+            // "name:password,name2:password2"
+            operators: env::var("IRC_OPERATORS").unwrap_or_default()
+                .split(',')
+                .filter_map(|operator| operator.split_once(':'))
+                .map(|(name, password)| (String::from(name), String::from(password)))
+                .collect()
+        }
+    }
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::from_env)
+}
+
+fn env_number(name: &str, default: usize) -> usize {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or(default),
+        Err(_) => default
+    }
+}
@@ -8,6 +8,7 @@ use crate::postman::{PostmanMessage, start_postman_thread};
 use std::collections::HashMap;
 use crate::registration::{start_registration_thread, RegistrationMessage};
 use crate::broadcast::{start_broadcaster_thread, BroadcastMessage};
+use crate::resolver::{Resolver, SystemResolver};
 
 mod client_handler;
 mod protocol;
@@ -15,6 +16,13 @@ mod channels;
 mod postman;
 mod registration;
 mod broadcast;
+mod message;
+mod config;
+mod clock;
+mod casemapping;
+mod modes;
+mod resolver;
+mod cloak;
 
 fn main() {
     let listener = match TcpListener::bind("0.0.0.0:3333") {
@@ -51,6 +59,7 @@ fn main() {
         channels.clone(),
         channel_rx,
         broadcast_tx.clone(),
+        registration_tx.clone(),
         postman_tx.clone()
     );
 
@@ -67,7 +76,10 @@ fn main() {
         channels.clone()
     );
 
-    println!("Server listening on port 3333");
+    // Looks up the hostnames of connecting clients
+    let resolver: Arc<dyn Resolver> = Arc::new(SystemResolver);
+
+    println!("Server {} listening on port 3333", config::get().server_name);
 
     // Accept connection for each new client
     for stream in listener.incoming() {
@@ -78,7 +90,7 @@ fn main() {
                 let ctx = channel_tx.clone();
 
                 start_client_thread(
-                    stream, btx, rtx, ctx);
+                    stream, btx, rtx, ctx, resolver.clone());
             }
             Err(e) => {
                 println!("Error when accepting new client: {}", e);
@@ -0,0 +1,333 @@
+use std::fmt;
+
+// RFC 1459: a message has at most 15 parameters, the 15th being the trailing one
+const MAX_MIDDLE_PARAMS: usize = 14;
+
+// RFC 1459: a message is at most 512 bytes long, CRLF included
+pub const MAX_LINE_LENGTH: usize = 512;
+
+// IRCv3 message tag: "@key=value;key2 ..."
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub key: String,
+    pub value: Option<String>
+}
+
+// IRC message (RFC 1459/2812): "[@tags] [:prefix] COMMAND [middle...] [:trailing]"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub tags: Vec<Tag>,
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+    pub trailing: Option<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    Empty,
+    MissingCommand,
+    InvalidCommand(String)
+}
+
+impl Message {
+    pub fn new(command: &str) -> Message {
+        Message {
+            tags: Vec::new(),
+            prefix: None,
+            command: String::from(command),
+            params: Vec::new(),
+            trailing: None
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: &str) -> Message {
+        self.prefix = Some(String::from(prefix));
+        self
+    }
+
+    pub fn with_param(mut self, param: &str) -> Message {
+        self.params.push(String::from(param));
+        self
+    }
+
+    pub fn with_trailing(mut self, trailing: &str) -> Message {
+        self.trailing = Some(String::from(trailing));
+        self
+    }
+
+    // Parses a single line received from a client, with or without its CRLF
+    pub fn parse(line: &str) -> Result<Message, ParseError> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start_matches(' ');
+
+        if rest.is_empty() {
+            return Err(ParseError::Empty)
+        }
+
+        let mut tags = Vec::new();
+        if let Some(stripped) = rest.strip_prefix('@') {
+            let (raw_tags, remaining) = split_word(stripped);
+            tags = parse_tags(raw_tags);
+            rest = remaining;
+        }
+
+        let mut prefix = None;
+        if let Some(stripped) = rest.strip_prefix(':') {
+            let (raw_prefix, remaining) = split_word(stripped);
+            prefix = Some(String::from(raw_prefix));
+            rest = remaining;
+        }
+
+        let (command, mut rest) = split_word(rest);
+        if command.is_empty() {
+            return Err(ParseError::MissingCommand)
+        }
+
+        let is_numeric = command.len() == 3 && command.chars().all(|c| c.is_ascii_digit());
+        if !is_numeric && !command.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(ParseError::InvalidCommand(String::from(command)))
+        }
+
+        let mut params = Vec::new();
+        let mut trailing = None;
+
+        while !rest.is_empty() {
+            if let Some(stripped) = rest.strip_prefix(':') {
+                trailing = Some(String::from(stripped));
+                break
+            }
+
+            // Past the 14th middle parameter, the remaining of the line is the trailing one
+            if params.len() == MAX_MIDDLE_PARAMS {
+                trailing = Some(String::from(rest));
+                break
+            }
+
+            let (param, remaining) = split_word(rest);
+            params.push(String::from(param));
+            rest = remaining;
+        }
+
+        Ok(Message {
+            tags,
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+            trailing
+        })
+    }
+
+    // Returns the nth parameter, the trailing one being the last
+    pub fn param(&self, index: usize) -> Option<&str> {
+        if index < self.params.len() {
+            return Some(&self.params[index])
+        }
+
+        if index == self.params.len() {
+            return self.trailing.as_deref()
+        }
+
+        None
+    }
+
+    pub fn param_count(&self) -> usize {
+        self.params.len() + self.trailing.is_some() as usize
+    }
+
+    // Serialized message, ready to be written on a stream
+    pub fn to_line(&self) -> String {
+        format!("{}\r\n", self)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags.iter()
+                .map(|tag| match &tag.value {
+                    Some(value) => format!("{}={}", sanitize_word(&tag.key), escape_tag_value(value)),
+                    None => sanitize_word(&tag.key)
+                })
+                .collect();
+
+            write!(f, "@{} ", tags.join(";"))?;
+        }
+
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{} ", sanitize_word(prefix))?;
+        }
+
+        write!(f, "{}", sanitize_word(&self.command))?;
+
+        let last_index = self.params.len().saturating_sub(1);
+        for (index, param) in self.params.iter().enumerate() {
+            let param = strip_line_breaks(param);
+
+            // A middle parameter that cannot be sent as such is only allowed as the last one
+            if param.is_empty() || param.starts_with(':') || param.contains(' ') {
+                if index == last_index && self.trailing.is_none() {
+                    return write!(f, " :{}", param)
+                }
+                write!(f, " {}", sanitize_word(param.trim_start_matches(':')))?;
+                continue
+            }
+
+            write!(f, " {}", param)?;
+        }
+
+        if let Some(trailing) = &self.trailing {
+            write!(f, " :{}", strip_line_breaks(trailing))?;
+        }
+
+        Ok(())
+    }
+}
+
+// Splits a string on the first space, skipping the following spaces
+fn split_word(input: &str) -> (&str, &str) {
+    match input.find(' ') {
+        Some(index) => (&input[..index], input[index..].trim_start_matches(' ')),
+        None => (input, "")
+    }
+}
+
+fn parse_tags(raw_tags: &str) -> Vec<Tag> {
+    raw_tags.split(';')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| match tag.find('=') {
+            Some(index) => Tag {
+                key: String::from(&tag[..index]),
+                value: Some(unescape_tag_value(&tag[index + 1..]))
+            },
+            None => Tag {
+                key: String::from(tag),
+                value: None
+            }
+        })
+        .collect()
+}
+
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue
+        }
+
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+// Removes the characters that would let a parameter inject another message
+fn strip_line_breaks(input: &str) -> String {
+    input.chars().filter(|c| !matches!(c, '\r' | '\n' | '\0')).collect()
+}
+
+fn sanitize_word(input: &str) -> String {
+    strip_line_breaks(input).replace(' ', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_prefix() {
+        let message = Message::parse(":nick!user@host PRIVMSG #rust :hello\r\n").unwrap();
+
+        assert_eq!(message.prefix.as_deref(), Some("nick!user@host"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, vec!["#rust"]);
+        assert_eq!(message.trailing.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn parses_and_unescapes_tags() {
+        let message = Message::parse("@id=a\\sb\\:c\\\\d;draft/flag :server NOTICE nick :hi").unwrap();
+
+        assert_eq!(message.tags, vec![
+            Tag { key: String::from("id"), value: Some(String::from("a b;c\\d")) },
+            Tag { key: String::from("draft/flag"), value: None }
+        ]);
+        assert_eq!(message.prefix.as_deref(), Some("server"));
+        assert_eq!(message.command, "NOTICE");
+    }
+
+    #[test]
+    fn escapes_tags() {
+        let mut message = Message::new("TAGMSG").with_param("#rust");
+        message.tags.push(Tag { key: String::from("id"), value: Some(String::from("a b;c\\d\r\n")) });
+
+        assert_eq!(message.to_string(), "@id=a\\sb\\:c\\\\d\\r\\n TAGMSG #rust");
+        assert_eq!(Message::parse(&message.to_line()).unwrap(), message);
+    }
+
+    #[test]
+    fn keeps_colons_in_trailing() {
+        let message = Message::parse("PRIVMSG #rust :see https://example.com: it works :)").unwrap();
+
+        assert_eq!(message.params, vec!["#rust"]);
+        assert_eq!(message.trailing.as_deref(), Some("see https://example.com: it works :)"));
+        assert_eq!(message.param(1), Some("see https://example.com: it works :)"));
+        assert_eq!(message.param_count(), 2);
+    }
+
+    #[test]
+    fn limits_parameters_to_fifteen() {
+        let line = "CMD a b c d e f g h i j k l m n o p q";
+        let message = Message::parse(line).unwrap();
+
+        assert_eq!(message.params.len(), 14);
+        assert_eq!(message.params[13], "n");
+        assert_eq!(message.trailing.as_deref(), Some("o p q"));
+        assert_eq!(message.param_count(), 15);
+    }
+
+    #[test]
+    fn rejects_empty_lines() {
+        assert_eq!(Message::parse(""), Err(ParseError::Empty));
+        assert_eq!(Message::parse("\r\n"), Err(ParseError::Empty));
+        assert_eq!(Message::parse("    \r\n"), Err(ParseError::Empty));
+        assert_eq!(Message::parse(":prefix"), Err(ParseError::MissingCommand));
+    }
+
+    #[test]
+    fn strips_line_breaks_when_serializing() {
+        let message = Message::new("PRIVMSG")
+            .with_prefix("nick!user@host")
+            .with_param("#ru\r\nst")
+            .with_trailing("hello\r\nQUIT :injected");
+
+        assert_eq!(message.to_string(), ":nick!user@host PRIVMSG #rust :helloQUIT :injected");
+        assert_eq!(message.to_line(), ":nick!user@host PRIVMSG #rust :helloQUIT :injected\r\n");
+    }
+}
@@ -0,0 +1,270 @@
+use crate::config;
+
+// Channel modes (RFC 2811 4.2)
+#[derive(Clone, Default)]
+pub struct ChannelModes {
+    // n: only members can send messages to the channel
+    pub no_external: bool,
+    // t: only operators can change the topic
+    pub topic_ops_only: bool,
+    // m: only voiced members and above can send messages to the channel
+    pub moderated: bool,
+    // i: joining requires an invitation
+    pub invite_only: bool,
+    // k: joining requires a key
+    pub key: Option<String>,
+    // l: maximum number of members
+    pub limit: Option<usize>,
+    // s: channel hidden from non members
+    pub secret: bool,
+    // p: channel name hidden from non members' WHOIS
+    pub private: bool
+}
+
+// User modes
+#[derive(Clone, Default)]
+pub struct UserModes {
+    // i: hidden from WHO and NAMES of clients sharing no channel with the user
+    pub invisible: bool,
+    // o: IRC operator, given by OPER
+    pub operator: bool,
+    // w: receives WALLOPS
+    pub wallops: bool,
+    // x: hostname replaced by a cloak
+    pub cloaked: bool
+}
+
+// User modes advertised in RPL_MYINFO, and those only the server can set
+pub const USER_MODES: &str = "iowx";
+pub const SERVER_USER_MODES: &str = "o";
+
+impl UserModes {
+    // Returns false if the mode is unknown
+    pub fn set_flag(&mut self, mode: char, value: bool) -> bool {
+        match mode {
+            'i' => self.invisible = value,
+            'o' => self.operator = value,
+            'w' => self.wallops = value,
+            'x' => self.cloaked = value,
+            _ => return false
+        }
+        true
+    }
+
+    pub fn has_flag(&self, mode: char) -> bool {
+        match mode {
+            'i' => self.invisible,
+            'o' => self.operator,
+            'w' => self.wallops,
+            'x' => self.cloaked,
+            _ => false
+        }
+    }
+
+    // Mode string sent in RPL_UMODEIS
+    pub fn to_flags(&self) -> String {
+        let mut flags = String::from("+");
+        for mode in USER_MODES.chars() {
+            if self.has_flag(mode) {
+                flags.push(mode);
+            }
+        }
+        flags
+    }
+}
+
+// A single "+x param" or "-x param" taken from a MODE command
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeChange {
+    pub adding: bool,
+    pub mode: char,
+    pub param: Option<String>
+}
+
+// Channel membership modes from highest to lowest, with the prefix shown in NAMES
+pub const MEMBERSHIP_MODES: [(char, char); 4] = [('q', '~'), ('o', '@'), ('h', '%'), ('v', '+')];
+
+// Membership modes enabled on this server, advertised with PREFIX
+pub fn membership_modes() -> Vec<(char, char)> {
+    let enabled = &config::get().prefix_modes;
+    MEMBERSHIP_MODES.iter()
+        .filter(|(mode, _)| enabled.contains(*mode))
+        .cloned()
+        .collect()
+}
+
+// Rank of a membership mode, 0 being the highest
+pub fn membership_rank(mode: char) -> Option<usize> {
+    MEMBERSHIP_MODES.iter().position(|(m, _)| *m == mode)
+}
+
+// Channel modes taking no parameter, modes taking one when set, and list modes (bans, ban
+// exceptions and invite exceptions) advertised with CHANMODES
+pub const CHANNEL_FLAG_MODES: &str = "imnpst";
+pub const CHANNEL_PARAM_MODES: &str = "kl";
+pub const CHANNEL_LIST_MODES: &str = "beI";
+
+// Entry of a ban, ban exception or invite exception list
+#[derive(Clone)]
+pub struct MaskEntry {
+    pub mask: String,
+    pub set_by: String,
+    pub set_at: u64
+}
+
+// Completes a partial mask: "nick" becomes "nick!*@*" and "user@host" becomes "*!user@host"
+pub fn normalize_mask(mask: &str) -> String {
+    let (nick, rest) = match mask.find('!') {
+        Some(index) => (&mask[..index], &mask[index + 1..]),
+        None if mask.contains('@') => ("*", mask),
+        None => (mask, "*")
+    };
+
+    let (user, host) = match rest.find('@') {
+        Some(index) => (&rest[..index], &rest[index + 1..]),
+        None => (rest, "*")
+    };
+
+    let or_wildcard = |part: &str| if part.is_empty() { String::from("*") } else { String::from(part) };
+    format!("{}!{}@{}", or_wildcard(nick), or_wildcard(user), or_wildcard(host))
+}
+
+impl ChannelModes {
+    pub fn from_flags(flags: &str) -> ChannelModes {
+        let mut modes = ChannelModes::default();
+        for flag in flags.chars() {
+            modes.set_flag(flag, true);
+        }
+        modes
+    }
+
+    // Returns false if the mode is not a flag
+    pub fn set_flag(&mut self, mode: char, value: bool) -> bool {
+        match mode {
+            'n' => self.no_external = value,
+            't' => self.topic_ops_only = value,
+            'm' => self.moderated = value,
+            'i' => self.invite_only = value,
+            's' => self.secret = value,
+            'p' => self.private = value,
+            _ => return false
+        }
+        true
+    }
+
+    pub fn has_flag(&self, mode: char) -> bool {
+        match mode {
+            'n' => self.no_external,
+            't' => self.topic_ops_only,
+            'm' => self.moderated,
+            'i' => self.invite_only,
+            's' => self.secret,
+            'p' => self.private,
+            _ => false
+        }
+    }
+
+    // Mode string and its parameters, as sent in RPL_CHANNELMODEIS. The key is only shown to members.
+    pub fn to_params(&self, show_key: bool) -> Vec<String> {
+        let mut flags = String::from("+");
+        let mut params = Vec::new();
+
+        for mode in CHANNEL_FLAG_MODES.chars() {
+            if self.has_flag(mode) {
+                flags.push(mode);
+            }
+        }
+
+        if let Some(key) = &self.key {
+            flags.push('k');
+            if show_key {
+                params.push(key.clone());
+            }
+        }
+
+        if let Some(limit) = self.limit {
+            flags.push('l');
+            params.push(limit.to_string());
+        }
+
+        params.insert(0, flags);
+        params
+    }
+}
+
+// Reads "+nt-l+k key" style arguments. `takes_param` tells whether a mode uses a parameter
+// when added or removed; missing parameters are left to None.
+pub fn parse_mode_changes<F>(args: &[String], takes_param: F) -> Vec<ModeChange>
+    where F: Fn(char, bool) -> bool {
+    let mut changes = Vec::new();
+    let mut params = args.iter().skip(1);
+
+    let modestring = match args.first() {
+        Some(modestring) => modestring,
+        None => return changes
+    };
+
+    let mut adding = true;
+    for mode in modestring.chars() {
+        match mode {
+            '+' => adding = true,
+            '-' => adding = false,
+            _ => {
+                let param = match takes_param(mode, adding) {
+                    true => params.next().cloned(),
+                    false => None
+                };
+
+                changes.push(ModeChange { adding, mode, param });
+            }
+        }
+    }
+
+    changes
+}
+
+// Turns applied changes back into "+nt-l" and its parameters, as relayed in MODE messages
+pub fn format_mode_changes(changes: &[ModeChange]) -> Vec<String> {
+    let mut modestring = String::new();
+    let mut params = Vec::new();
+    let mut current_sign = None;
+
+    for change in changes {
+        if current_sign != Some(change.adding) {
+            modestring.push(if change.adding { '+' } else { '-' });
+            current_sign = Some(change.adding);
+        }
+
+        modestring.push(change.mode);
+
+        if let Some(param) = &change.param {
+            params.push(param.clone());
+        }
+    }
+
+    params.insert(0, modestring);
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_nickname_masks() {
+        assert_eq!(normalize_mask("nick"), "nick!*@*");
+        assert_eq!(normalize_mask("nick!user"), "nick!user@*");
+    }
+
+    #[test]
+    fn completes_user_and_host_masks() {
+        assert_eq!(normalize_mask("user@host"), "*!user@host");
+        assert_eq!(normalize_mask("@host"), "*!*@host");
+    }
+
+    #[test]
+    fn keeps_full_masks() {
+        assert_eq!(normalize_mask("*!*@*.example.com"), "*!*@*.example.com");
+        assert_eq!(normalize_mask("!@"), "*!*@*");
+    }
+}
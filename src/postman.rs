@@ -33,7 +33,7 @@ pub fn start_postman_thread(postman_rx: Receiver<PostmanMessage>, register_tx: S
             let mut writer = BufWriter::new(stream);
 
             // Write to stream
-            match writer.write(msg.content.as_bytes()) {
+            match writer.write_all(msg.content.as_bytes()) {
                 Ok(_) => {},
                 Err(e) => {
                     println!("Unable to write to message to stream: {:?}", e);
@@ -47,9 +47,14 @@ pub fn start_postman_thread(postman_rx: Receiver<PostmanMessage>, register_tx: S
                     // Error handling when client is disconnected
                     println!("Unable to flush stream: {:?}", e);
                     if e.kind() == ErrorKind::BrokenPipe || e.kind() == ErrorKind::ConnectionReset {
+                        let reason = match e.kind() {
+                            ErrorKind::BrokenPipe => "Broken pipe",
+                            _ => "Connection reset by peer"
+                        };
+
                         let unregister_message = RegistrationMessage {
                             client: msg.client.clone(),
-                            action: RegistrationAction::LEAVE
+                            action: RegistrationAction::Leave(String::from(reason))
                         };
 
                         // Unregister client
@@ -70,10 +75,9 @@ pub fn start_postman_thread(postman_rx: Receiver<PostmanMessage>, register_tx: S
 
 pub fn send_message(message: PostmanMessage, postman_tx: Sender<PostmanMessage>) {
     match postman_tx.send(message) {
-        Ok(_) => return,
+        Ok(_) => {},
         Err(e) => {
             println!("Unable to write PostmanMessage to the channel: {:?}", e);
-            return
         }
     }
 }
\ No newline at end of file
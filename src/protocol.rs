@@ -1,46 +1,665 @@
 use crate::channels::Channel;
+use crate::client_handler::Client;
+use crate::clock::format_timestamp;
+use crate::config;
+use crate::message::{Message, MAX_LINE_LENGTH};
+use crate::modes::{CHANNEL_FLAG_MODES, CHANNEL_PARAM_MODES, CHANNEL_LIST_MODES, USER_MODES, MaskEntry, membership_modes};
 
-fn generate_response(code: u16, nick: String, content: String) -> String {
-    format!(":guyot-gondange.fr {:03} {} :{}\r\n", code, nick, content)
+// Numeric replies defined by RFC 2812 (section 5), plus the widely deployed 005, 329 and 333
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Numeric {
+    RplWelcome = 1,
+    RplYourHost = 2,
+    RplCreated = 3,
+    RplMyInfo = 4,
+    RplISupport = 5,
+    RplUModeIs = 221,
+    RplAway = 301,
+    RplUnAway = 305,
+    RplNowAway = 306,
+    RplWhoisUser = 311,
+    RplWhoisServer = 312,
+    RplWhoisOperator = 313,
+    RplWhowasUser = 314,
+    RplEndOfWho = 315,
+    RplWhoisIdle = 317,
+    RplEndOfWhois = 318,
+    RplWhoisChannels = 319,
+    RplListStart = 321,
+    RplList = 322,
+    RplListEnd = 323,
+    RplChannelModeIs = 324,
+    RplCreationTime = 329,
+    RplNoTopic = 331,
+    RplTopic = 332,
+    RplTopicWhoTime = 333,
+    RplInviting = 341,
+    RplInviteList = 346,
+    RplEndOfInviteList = 347,
+    RplExceptList = 348,
+    RplEndOfExceptList = 349,
+    RplWhoReply = 352,
+    RplWhoSpcRpl = 354,
+    RplNamReply = 353,
+    RplEndOfNames = 366,
+    RplBanList = 367,
+    RplEndOfBanList = 368,
+    RplEndOfWhowas = 369,
+    RplWhoisHost = 378,
+    RplYoureOper = 381,
+    RplHostHidden = 396,
+    ErrNoSuchNick = 401,
+    ErrNoSuchChannel = 403,
+    ErrCannotSendToChan = 404,
+    ErrTooManyChannels = 405,
+    ErrWasNoSuchNick = 406,
+    ErrTooManyTargets = 407,
+    ErrNoOrigin = 409,
+    ErrNoRecipient = 411,
+    ErrNoTextToSend = 412,
+    ErrUnknownCommand = 421,
+    ErrNoNicknameGiven = 431,
+    ErrErroneusNickname = 432,
+    ErrNicknameInUse = 433,
+    ErrUserNotInChannel = 441,
+    ErrNotOnChannel = 442,
+    ErrUserOnChannel = 443,
+    ErrNotRegistered = 451,
+    ErrNeedMoreParams = 461,
+    ErrAlreadyRegistred = 462,
+    ErrPasswdMismatch = 464,
+    ErrKeySet = 467,
+    ErrChannelIsFull = 471,
+    ErrUnknownMode = 472,
+    ErrInviteOnlyChan = 473,
+    ErrBannedFromChan = 474,
+    ErrBadChannelKey = 475,
+    ErrBadChanMask = 476,
+    ErrBanListFull = 478,
+    ErrNoPrivileges = 481,
+    ErrChanOPrivsNeeded = 482,
+    ErrNoOperHost = 491,
+    ErrUModeUnknownFlag = 501,
+    ErrUsersDontMatch = 502
 }
 
-pub fn welcome_reply(nick: String) -> String {
-    generate_response(001, nick, String::from("Bienvenue sur notre serveur sûr et efficace !"))
+impl Numeric {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+}
+
+// Numeric reply sent by the server: ":server 123 target [params...] [:trailing]"
+#[derive(Clone)]
+pub struct Reply {
+    numeric: Numeric,
+    target: String,
+    params: Vec<String>,
+    trailing: Option<String>
+}
+
+impl Reply {
+    pub fn new(numeric: Numeric, target: &str) -> Reply {
+        Reply {
+            numeric,
+            target: String::from(target),
+            params: Vec::new(),
+            trailing: None
+        }
+    }
+
+    pub fn param(mut self, param: &str) -> Reply {
+        self.params.push(String::from(param));
+        self
+    }
+
+    pub fn trailing(mut self, trailing: &str) -> Reply {
+        self.trailing = Some(String::from(trailing));
+        self
+    }
+
+    pub fn to_message(&self) -> Message {
+        let mut message = Message::new(&format!("{:03}", self.numeric.code()))
+            .with_prefix(&config::get().server_name)
+            .with_param(&self.target);
+
+        for param in self.params.iter() {
+            message = message.with_param(param);
+        }
+
+        if let Some(trailing) = &self.trailing {
+            message = message.with_trailing(trailing);
+        }
+
+        message
+    }
+
+    pub fn to_line(&self) -> String {
+        self.to_message().to_line()
+    }
+
+    // Sends the items separated by spaces in the trailing parameter, on as many lines as needed
+    // to stay within the line length limit
+    pub fn trailing_list(self, items: &[String]) -> String {
+        // Room left once the rest of the line, the " :" before the trailing and CRLF are written
+        let room = MAX_LINE_LENGTH.saturating_sub(self.to_message().to_string().len() + 4);
+        let mut lines = String::new();
+        let mut current = String::new();
+
+        for item in items {
+            if !current.is_empty() && current.len() + 1 + item.len() > room {
+                lines.push_str(&self.clone().trailing(&current).to_line());
+                current.clear();
+            }
+
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(item);
+        }
+
+        if !current.is_empty() || lines.is_empty() {
+            lines.push_str(&self.trailing(&current).to_line());
+        }
+
+        lines
+    }
+}
+
+pub fn need_more_params(nick: &str, command: &str) -> String {
+    Reply::new(Numeric::ErrNeedMoreParams, nick)
+        .param(command)
+        .trailing("Not enough parameters")
+        .to_line()
 }
 
-pub fn join_message(nick: String, domain: String, channel: String) -> String {
-    format!(":{}!{}@{} JOIN {}\r\n", nick, nick, domain, channel)
+// Prefix identifying a user as the source of a message: "nick!user@host"
+pub fn user_prefix(nick: &str, user: &str, host: &str) -> String {
+    format!("{}!{}@{}", nick, user, host)
 }
 
+// Replies 001 to 004 sent once a client completed its registration
+pub fn welcome_burst(nick: String, user: String, host: String) -> String {
+    let config = config::get();
+    let version = format!("irc-rs-{}", env!("CARGO_PKG_VERSION"));
+
+    let welcome = Reply::new(Numeric::RplWelcome, &nick)
+        .trailing(&format!(
+            "Bienvenue sur notre serveur sûr et efficace, {} !",
+            user_prefix(&nick, &user, &host)
+        ));
+
+    let your_host = Reply::new(Numeric::RplYourHost, &nick)
+        .trailing(&format!("Your host is {}, running version {}", config.server_name, version));
+
+    let created = Reply::new(Numeric::RplCreated, &nick)
+        .trailing(&format!("This server was created {}", format_timestamp(config.created_at)));
+
+    // Channel modes, then those taking a parameter
+    let membership: String = membership_modes().iter().map(|(mode, _)| *mode).collect();
+    let channel_modes = format!("{}{}{}{}", CHANNEL_LIST_MODES, CHANNEL_PARAM_MODES, CHANNEL_FLAG_MODES, membership);
+    let param_modes = format!("{}{}{}", CHANNEL_LIST_MODES, CHANNEL_PARAM_MODES, membership);
+
+    let my_info = Reply::new(Numeric::RplMyInfo, &nick)
+        .param(&config.server_name)
+        .param(&version)
+        .param(USER_MODES)
+        .param(&channel_modes)
+        .param(&param_modes);
+
+    let mut burst: String = [welcome, your_host, created, my_info].iter()
+        .map(|reply| reply.to_line())
+        .collect();
+
+    burst.push_str(&isupport(nick));
+    burst
+}
+
+// RPL_ISUPPORT lines advertising the server features, at most 13 tokens per line
+pub fn isupport(nick: String) -> String {
+    let config = config::get();
+    let (prefix_modes, prefixes): (String, String) = membership_modes().into_iter().unzip();
+    // Each list has its own limit, "beI:50" would be one limit shared by the three lists
+    let max_list: Vec<String> = CHANNEL_LIST_MODES.chars()
+        .map(|mode| format!("{}:{}", mode, config.max_list_entries))
+        .collect();
+    let max_list = max_list.join(",");
+
+    let tokens = [
+        format!("CASEMAPPING={}", config.casemapping.name()),
+        format!("NICKLEN={}", config.nick_length),
+        format!("USERLEN={}", config.user_length + 1),
+        format!("CHANTYPES={}", config.chantypes),
+        format!("CHANLIMIT={}:{}", config.chantypes, config.max_channels),
+        String::from("ELIST=MTU"),
+        String::from("WHOX"),
+        format!("CHANNELLEN={}", config.channel_length),
+        format!("TARGMAX=PRIVMSG:{},NOTICE:{}", config.max_targets, config.max_targets),
+        format!("TOPICLEN={}", config.topic_length),
+        format!("AWAYLEN={}", config.away_length),
+        format!("CHANMODES={},{},{}", CHANNEL_LIST_MODES, CHANNEL_PARAM_MODES, CHANNEL_FLAG_MODES),
+        format!("PREFIX=({}){}", prefix_modes, prefixes),
+        format!("MAXLIST={}", max_list),
+        String::from("EXCEPTS=e"),
+        String::from("INVEX=I")
+    ];
+
+    tokens.chunks(13)
+        .map(|tokens| {
+            let mut reply = Reply::new(Numeric::RplISupport, &nick);
+            for token in tokens {
+                reply = reply.param(token);
+            }
+            reply.trailing("are supported by this server").to_line()
+        })
+        .collect()
+}
+
+pub fn join_message(source: &Client, channel: String) -> String {
+    Message::new("JOIN")
+        .with_prefix(&source.hostmask())
+        .with_param(&channel)
+        .to_line()
+}
+
+// RPL_TOPIC and RPL_TOPICWHOTIME, or RPL_NOTOPIC if the channel has no topic
 pub fn join_header(nick: String, channel: &Channel) -> String {
-    format!(":guyot-gondange.fr {:03} {} {} :{}\r\n", 332, nick, channel.name, channel.description)
+    if channel.description.is_empty() {
+        return Reply::new(Numeric::RplNoTopic, &nick)
+            .param(&channel.name)
+            .trailing("No topic is set")
+            .to_line()
+    }
+
+    let topic = Reply::new(Numeric::RplTopic, &nick)
+        .param(&channel.name)
+        .trailing(&channel.description);
+
+    let topic_who_time = Reply::new(Numeric::RplTopicWhoTime, &nick)
+        .param(&channel.name)
+        .param(&channel.topic_setter)
+        .param(&channel.topic_time.to_string());
+
+    topic.to_line() + &topic_who_time.to_line()
+}
+
+pub fn topic_msg(source: &Client, channel: String, topic: String) -> String {
+    Message::new("TOPIC")
+        .with_prefix(&source.hostmask())
+        .with_param(&channel)
+        .with_trailing(&topic)
+        .to_line()
+}
+
+// RPL_NAMREPLY of the members the viewer can see
+pub fn join_members(viewer: &Client, channel: &Channel) -> String {
+    let members: Vec<String> = channel.members_visible_to(viewer).into_iter()
+        .map(|client| format!("{}{}", channel.prefix(client), client.nickname))
+        .collect();
+
+    // Secret channels are shown with "@", private ones with "*"
+    let symbol = match (channel.modes.secret, channel.modes.private) {
+        (true, _) => "@",
+        (false, true) => "*",
+        _ => "="
+    };
+
+    Reply::new(Numeric::RplNamReply, &viewer.nickname)
+        .param(symbol)
+        .param(&channel.name)
+        .trailing_list(&members)
+}
+
+// The channel name is "*" at the end of a NAMES listing every channel
+pub fn join_end_members(nick: String, channel_name: &str) -> String {
+    Reply::new(Numeric::RplEndOfNames, &nick)
+        .param(channel_name)
+        .trailing("End of NAMES list")
+        .to_line()
+}
+
+pub fn list_start(nick: String) -> String {
+    Reply::new(Numeric::RplListStart, &nick)
+        .param("Channel")
+        .trailing("Users  Name")
+        .to_line()
+}
+
+pub fn list_entry(nick: String, channel: &Channel) -> String {
+    Reply::new(Numeric::RplList, &nick)
+        .param(&channel.name)
+        .param(&channel.clients.len().to_string())
+        .trailing(&channel.description)
+        .to_line()
+}
+
+pub fn list_end(nick: String) -> String {
+    Reply::new(Numeric::RplListEnd, &nick)
+        .trailing("End of /LIST")
+        .to_line()
+}
+
+// Progress of the connection, sent before registration
+pub fn auth_notice(text: &str) -> String {
+    Message::new("NOTICE")
+        .with_prefix(&config::get().server_name)
+        .with_param("AUTH")
+        .with_trailing(text)
+        .to_line()
+}
+
+// Keepalive sent by the server to silent clients
+pub fn ping() -> String {
+    Message::new("PING")
+        .with_trailing(&config::get().server_name)
+        .to_line()
+}
+
+pub fn pong(token: String) -> String {
+    let server_name = &config::get().server_name;
+
+    Message::new("PONG")
+        .with_prefix(server_name)
+        .with_param(server_name)
+        .with_trailing(&token)
+        .to_line()
+}
+
+// PRIVMSG or NOTICE sent to a channel or a user
+pub fn text_msg(command: String, source: &Client, target: String, content: String) -> String {
+    Message::new(&command)
+        .with_prefix(&source.hostmask())
+        .with_param(&target)
+        .with_trailing(&content)
+        .to_line()
+}
+
+pub fn wallops_msg(source: &Client, content: &str) -> String {
+    Message::new("WALLOPS")
+        .with_prefix(&source.hostmask())
+        .with_trailing(content)
+        .to_line()
+}
+
+pub fn part_msg(source: &Client, channel: String, content: String) -> String {
+    Message::new("PART")
+        .with_prefix(&source.hostmask())
+        .with_param(&channel)
+        .with_trailing(&content)
+        .to_line()
 }
 
-pub fn join_members(nick: String, channel: &Channel) -> String {
+pub fn quit_msg(source: &Client, reason: String) -> String {
+    Message::new("QUIT")
+        .with_prefix(&source.hostmask())
+        .with_trailing(&reason)
+        .to_line()
+}
+
+// Last message sent to a client before its connection is closed
+pub fn closing_link(reason: &str) -> String {
+    Message::new("ERROR")
+        .with_trailing(&format!("Closing Link ({})", reason))
+        .to_line()
+}
+
+pub fn nick_msg(source: &Client, new_nick: String) -> String {
+    Message::new("NICK")
+        .with_prefix(&source.hostmask())
+        .with_trailing(&new_nick)
+        .to_line()
+}
+
+// RPL_CHANNELMODEIS followed by RPL_CREATIONTIME
+pub fn channel_mode_is(nick: String, channel: &Channel, show_key: bool) -> String {
+    let mut mode_is = Reply::new(Numeric::RplChannelModeIs, &nick)
+        .param(&channel.name);
+
+    for param in channel.modes.to_params(show_key) {
+        mode_is = mode_is.param(&param);
+    }
+
+    let creation_time = Reply::new(Numeric::RplCreationTime, &nick)
+        .param(&channel.name)
+        .param(&channel.created_at.to_string());
+
+    mode_is.to_line() + &creation_time.to_line()
+}
 
-    let mut connected_clients_string = String::new();
+pub fn mode_msg(source: &Client, target: String, changes: Vec<String>) -> String {
+    let mut message = Message::new("MODE")
+        .with_prefix(&source.hostmask())
+        .with_param(&target);
 
-    for  client in channel.clients.clone() {
-        connected_clients_string.push_str(&*client.username);
-        connected_clients_string.push_str(" ");
+    for change in changes {
+        message = message.with_param(&change);
     }
 
-    format!(":guyot-gondange.fr {:03} {} = {} :{}\r\n", 353, nick, channel.name, connected_clients_string)
+    message.to_line()
+}
+
+pub fn kick_msg(source: &Client, channel: String, target: String, reason: String) -> String {
+    Message::new("KICK")
+        .with_prefix(&source.hostmask())
+        .with_param(&channel)
+        .with_param(&target)
+        .with_trailing(&reason)
+        .to_line()
+}
+
+pub fn invite_msg(source: &Client, target: String, channel: String) -> String {
+    Message::new("INVITE")
+        .with_prefix(&source.hostmask())
+        .with_param(&target)
+        .with_trailing(&channel)
+        .to_line()
 }
 
-pub fn join_end_members(nick: String, channel: &Channel) -> String {
-    format!(":guyot-gondange.fr {:03} {} {} :{}\r\n", 366, nick, channel.name, "End of NAMES list")
+// Entries of a ban (367), ban exception (348) or invite exception (346) list, and the end of the list
+pub fn mask_list(nick: String, channel: &str, mode: char, entries: &[MaskEntry]) -> String {
+    let (entry_numeric, end_numeric, end_text) = match mode {
+        'e' => (Numeric::RplExceptList, Numeric::RplEndOfExceptList, "End of channel exception list"),
+        'I' => (Numeric::RplInviteList, Numeric::RplEndOfInviteList, "End of channel invite list"),
+        _ => (Numeric::RplBanList, Numeric::RplEndOfBanList, "End of channel ban list")
+    };
+
+    let mut content: String = entries.iter()
+        .map(|entry| Reply::new(entry_numeric, &nick)
+            .param(channel)
+            .param(&entry.mask)
+            .param(&entry.set_by)
+            .param(&entry.set_at.to_string())
+            .to_line())
+        .collect();
 
+    content.push_str(&Reply::new(end_numeric, &nick).param(channel).trailing(end_text).to_line());
+    content
 }
 
-pub fn pong(domain: String) -> String {
-    format!("PONG guyot-gondange.fr {}", domain)
+// WHOX field letters, in the order their values are sent in RPL_WHOSPCRPL
+const WHOX_FIELDS: &str = "tcuihsnfdlaor";
+
+// RPL_WHOREPLY, or RPL_WHOSPCRPL with the fields asked with "%fields[,token]". The address of
+// cloaked clients is only shown to operators and to the client itself.
+pub fn who_reply(nick: &str, channel: &str, client: &Client, flags: &str, whox: Option<&str>, show_address: bool) -> String {
+    let server_name = &config::get().server_name;
+
+    let whox = match whox {
+        Some(whox) => whox.trim_start_matches('%'),
+        None => {
+            return Reply::new(Numeric::RplWhoReply, nick)
+                .param(channel)
+                .param(&client.username)
+                .param(&client.host())
+                .param(server_name)
+                .param(&client.nickname)
+                .param(flags)
+                .trailing(&format!("0 {}", client.realname))
+                .to_line()
+        }
+    };
+
+    let (fields, token) = match whox.find(',') {
+        Some(index) => (&whox[..index], &whox[index + 1..]),
+        None => (whox, "0")
+    };
+
+    let mut reply = Reply::new(Numeric::RplWhoSpcRpl, nick);
+    for field in WHOX_FIELDS.chars().filter(|field| fields.contains(*field)) {
+        reply = match field {
+            't' => reply.param(token),
+            'c' => reply.param(channel),
+            'u' => reply.param(&client.username),
+            'i' if client.user_modes().cloaked && !show_address => reply.param("255.255.255.255"),
+            'i' => reply.param(&client.address.to_string()),
+            'h' => reply.param(&client.host()),
+            's' => reply.param(server_name),
+            'n' => reply.param(&client.nickname),
+            'f' => reply.param(flags),
+            'd' => reply.param("0"),
+            'l' => reply.param(&client.idle_time().to_string()),
+            // Accounts and operator levels are not supported
+            'a' => reply.param("0"),
+            'o' => reply.param("n/a"),
+            _ => reply.trailing(&client.realname)
+        };
+    }
+
+    reply.to_line()
 }
 
-pub fn priv_msg(nick: String, domain: String, channel: String, content: String) -> String {
-    format!(":{}!{}@{} PRIVMSG {} :{}\r\n", nick, nick, domain, channel, content)
+pub fn end_of_who(nick: &str, mask: &str) -> String {
+    Reply::new(Numeric::RplEndOfWho, nick)
+        .param(mask)
+        .trailing("End of WHO list")
+        .to_line()
 }
 
-pub fn part_msg(nick: String, domain: String, channel: String, content: String) -> String {
-    format!(":{}!{}@{} PART {} :{}\r\n", nick, nick, domain, channel, content)
-}
\ No newline at end of file
+// RPL_WHOISUSER, RPL_WHOISSERVER, RPL_AWAY, RPL_WHOISOPERATOR and RPL_WHOISHOST, the channels
+// and idle time being added by the channels thread. The real host is only shown to operators
+// and to the client itself.
+pub fn whois_user(nick: &str, client: &Client, show_real_host: bool) -> String {
+    let config = config::get();
+
+    let user = Reply::new(Numeric::RplWhoisUser, nick)
+        .param(&client.nickname)
+        .param(&client.username)
+        .param(&client.host())
+        .param("*")
+        .trailing(&client.realname);
+
+    let server = Reply::new(Numeric::RplWhoisServer, nick)
+        .param(&client.nickname)
+        .param(&config.server_name)
+        .trailing(&config.server_info);
+
+    let mut content = user.to_line() + &server.to_line();
+    if let Some(message) = client.away_message() {
+        content.push_str(&away(nick, &client.nickname, &message));
+    }
+
+    if client.user_modes().operator {
+        let operator = Reply::new(Numeric::RplWhoisOperator, nick)
+            .param(&client.nickname)
+            .trailing("is an IRC operator");
+        content.push_str(&operator.to_line());
+    }
+
+    if show_real_host {
+        let host = Reply::new(Numeric::RplWhoisHost, nick)
+            .param(&client.nickname)
+            .trailing(&format!("is connecting from *@{} {}", client.domain, client.address));
+        content.push_str(&host.to_line());
+    }
+
+    content
+}
+
+// RPL_AWAY, sent with WHOIS and to the senders of private messages
+pub fn away(nick: &str, target: &str, message: &str) -> String {
+    Reply::new(Numeric::RplAway, nick)
+        .param(target)
+        .trailing(message)
+        .to_line()
+}
+
+// Channels prefixed with the membership of the client, split over several lines if needed
+pub fn whois_channels(nick: &str, target: &str, channels: &[String]) -> String {
+    Reply::new(Numeric::RplWhoisChannels, nick)
+        .param(target)
+        .trailing_list(channels)
+}
+
+pub fn whois_idle(nick: &str, client: &Client) -> String {
+    Reply::new(Numeric::RplWhoisIdle, nick)
+        .param(&client.nickname)
+        .param(&client.idle_time().to_string())
+        .param(&client.signon.to_string())
+        .trailing("seconds idle, signon time")
+        .to_line()
+}
+
+pub fn end_of_whois(nick: &str, target: &str) -> String {
+    Reply::new(Numeric::RplEndOfWhois, nick)
+        .param(target)
+        .trailing("End of /WHOIS list")
+        .to_line()
+}
+
+// RPL_WHOWASUSER, followed by the server the user was on and when they left
+pub fn whowas_user(nick: &str, nickname: &str, username: &str, host: &str, realname: &str, left_at: u64) -> String {
+    let user = Reply::new(Numeric::RplWhowasUser, nick)
+        .param(nickname)
+        .param(username)
+        .param(host)
+        .param("*")
+        .trailing(realname);
+
+    let server = Reply::new(Numeric::RplWhoisServer, nick)
+        .param(nickname)
+        .param(&config::get().server_name)
+        .trailing(&format_timestamp(left_at));
+
+    user.to_line() + &server.to_line()
+}
+
+pub fn end_of_whowas(nick: &str, target: &str) -> String {
+    Reply::new(Numeric::RplEndOfWhowas, nick)
+        .param(target)
+        .trailing("End of WHOWAS")
+        .to_line()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_names_within_line_length() {
+        let nick = "a".repeat(30);
+        let channel = format!("#{}", "c".repeat(49));
+        let members: Vec<String> = (0..200)
+            .map(|index| format!("@{:0>29}", index))
+            .collect();
+
+        let content = Reply::new(Numeric::RplNamReply, &nick)
+            .param("=")
+            .param(&channel)
+            .trailing_list(&members);
+
+        let lines: Vec<&str> = content.split_inclusive("\r\n").collect();
+        assert!(lines.len() > 1);
+
+        let mut listed = Vec::new();
+        for line in lines {
+            assert!(line.len() <= MAX_LINE_LENGTH, "{} bytes: {}", line.len(), line);
+            assert!(line.ends_with("\r\n"));
+
+            let message = Message::parse(line).unwrap();
+            assert_eq!(message.params, vec![nick.clone(), String::from("="), channel.clone()]);
+            listed.extend(message.trailing.unwrap().split(' ').map(String::from));
+        }
+
+        assert_eq!(listed, members);
+    }
+}
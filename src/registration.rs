@@ -3,12 +3,56 @@ use crate::client_handler::Client;
 use std::thread;
 use std::sync::{Arc, Mutex};
 use crate::postman::{PostmanMessage, send_message};
-use crate::protocol::welcome_reply;
-use crate::channels::{ChannelMessage};
+use crate::protocol::{welcome_burst, nick_msg, wallops_msg, who_reply, end_of_who, whois_user, end_of_whois, whowas_user, end_of_whowas, away, Reply, Numeric};
+use crate::clock::unix_timestamp;
+use std::collections::VecDeque;
+use crate::config;
+use crate::channels::{ChannelMessage, ChannelAction};
+use uuid::Uuid;
 
 pub enum RegistrationAction {
-    REGISTER,
-    LEAVE
+    // The result of the registration is sent back to the client thread
+    Register(Sender<bool>),
+    // Same for nickname changes of registered clients
+    ChangeNickname(String, Sender<bool>),
+    // PRIVMSG or NOTICE to deliver to the client using the target nickname
+    DirectMessage {
+        target: String,
+        content: String,
+        notice: bool
+    },
+    // INVITE of the client using the target nickname, forwarded to the channels thread
+    Invite {
+        target: String,
+        channel: String
+    },
+    // WHO of the clients matching the mask, with the WHOX fields if given. Peers are the ids of
+    // the clients sharing a channel with the client, who see it even when invisible.
+    Who {
+        mask: String,
+        whox: Option<String>,
+        peers: Vec<Uuid>
+    },
+    // WHOIS of the client using the target nickname, completed by the channels thread
+    Whois(String),
+    // WHOWAS of the target nickname, limited to count entries if given
+    Whowas {
+        target: String,
+        count: Option<usize>
+    },
+    // WALLOPS sent by an operator to every client with +w
+    Wallops(String),
+    // The client disconnected, with the given QUIT reason
+    Leave(String)
+}
+
+// Client that disconnected or changed nickname, remembered for WHOWAS
+struct WhowasEntry {
+    nickname: String,
+    username: String,
+    host: String,
+    realname: String,
+    left_at: u64
 }
 
 pub struct RegistrationMessage {
@@ -24,6 +68,9 @@ pub fn start_registration_thread(
     postman_tx: Sender<PostmanMessage>
 ) {
     thread::spawn(move || {
+        // Most recent entries first
+        let mut history: VecDeque<WhowasEntry> = VecDeque::new();
+
         loop {
             // Listen to the channel
             let registration_message =  match registration_rx.recv() {
@@ -36,49 +83,462 @@ pub fn start_registration_thread(
 
             match registration_message.action {
                 // Registers a client
-                RegistrationAction::REGISTER => {
-                    register_client(
+                RegistrationAction::Register(result_tx) => {
+                    let registered = register_client(
                         registration_message.client,
                         clients.clone(),
                         postman_tx.clone()
                     );
+
+                    match result_tx.send(registered) {
+                        Ok(_) => {},
+                        Err(e) => {
+                            println!("Unable to send registration result: {:?}", e);
+                        }
+                    }
+                },
+                RegistrationAction::ChangeNickname(nickname, result_tx) => {
+                    let changed = change_nickname(
+                        registration_message.client,
+                        nickname,
+                        clients.clone(),
+                        &mut history,
+                        channels_tx.clone(),
+                        postman_tx.clone()
+                    );
+
+                    match result_tx.send(changed) {
+                        Ok(_) => {},
+                        Err(e) => {
+                            println!("Unable to send nickname change result: {:?}", e);
+                        }
+                    }
+                },
+                RegistrationAction::DirectMessage { target, content, notice } => {
+                    send_direct_message(
+                        registration_message.client,
+                        target,
+                        content,
+                        notice,
+                        clients.clone(),
+                        postman_tx.clone()
+                    );
+                },
+                RegistrationAction::Invite { target, channel } => {
+                    invite_client(
+                        registration_message.client,
+                        target,
+                        channel,
+                        clients.clone(),
+                        channels_tx.clone(),
+                        postman_tx.clone()
+                    );
+                },
+                RegistrationAction::Who { mask, whox, peers } => {
+                    who_clients(registration_message.client, mask, whox, peers, clients.clone(), postman_tx.clone());
+                },
+                RegistrationAction::Whois(target) => {
+                    whois_client(
+                        registration_message.client,
+                        target,
+                        clients.clone(),
+                        channels_tx.clone(),
+                        postman_tx.clone()
+                    );
+                },
+                RegistrationAction::Whowas { target, count } => {
+                    whowas_client(registration_message.client, target, count, &history, postman_tx.clone());
+                },
+                RegistrationAction::Wallops(content) => {
+                    send_wallops(registration_message.client, content, clients.clone(), postman_tx.clone());
                 },
                 // Unregister a client
-                RegistrationAction::LEAVE => {
-                    unregister_client(registration_message.client, clients.clone(), channels_tx.clone());
+                RegistrationAction::Leave(reason) => {
+                    unregister_client(
+                        registration_message.client,
+                        reason,
+                        clients.clone(),
+                        &mut history,
+                        channels_tx.clone()
+                    );
                 }
             }
-
-
-
         }
     });
 }
 
-fn register_client(client: Client, clients: Arc<Mutex<Vec<Client>>>, postman_tx: Sender<PostmanMessage>) {
+// Returns true if the client has been registered
+fn register_client(client: Client, clients: Arc<Mutex<Vec<Client>>>, postman_tx: Sender<PostmanMessage>) -> bool {
+    let mut clients = match clients.lock() {
+        Ok(clients) => clients,
+        Err(e) => {
+            println!("Register: Unable to acquire clients lock: {:?}", e);
+            return false
+        }
+    };
+
+    if !is_valid_nickname(&client.nickname) {
+        let reply = Reply::new(Numeric::ErrErroneusNickname, "*")
+            .param(&client.nickname)
+            .trailing("Erroneous nickname");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return false
+    }
+
+    if find_client_by_nickname(&clients, &client.nickname).is_some() {
+        let reply = Reply::new(Numeric::ErrNicknameInUse, "*")
+            .param(&client.nickname)
+            .trailing("Nickname is already in use");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return false
+    }
+
     // Say Hello to new client
     let msg = PostmanMessage {
         client: client.clone(),
-        content: welcome_reply(client.username.clone())
+        content: welcome_burst(client.nickname.clone(), client.username.clone(), client.host())
     };
 
-    send_message(msg, postman_tx.clone());
+    send_message(msg, postman_tx);
+
+    println!("New client registered: {:?}", client.nickname.clone());
+    // Add new client to connected clients vector
+    clients.push(client);
 
+    true
+}
+
+// Returns true if the client now uses the new nickname
+fn change_nickname(
+    client: Client,
+    nickname: String,
+    clients: Arc<Mutex<Vec<Client>>>,
+    history: &mut VecDeque<WhowasEntry>,
+    channels_tx: Sender<ChannelMessage>,
+    postman_tx: Sender<PostmanMessage>
+) -> bool {
     let mut clients = match clients.lock() {
         Ok(clients) => clients,
         Err(e) => {
-            println!("Register: Unable to acquire clients lock: {:?}", e);
+            println!("Nick: Unable to acquire clients lock: {:?}", e);
+            return false
+        }
+    };
+
+    if !is_valid_nickname(&nickname) {
+        let reply = Reply::new(Numeric::ErrErroneusNickname, &client.nickname)
+            .param(&nickname)
+            .trailing("Erroneous nickname");
+        send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+        return false
+    }
+
+    // A client may change the case of its own nickname
+    if let Some(owner) = find_client_by_nickname(&clients, &nickname) {
+        if *owner != client {
+            let reply = Reply::new(Numeric::ErrNicknameInUse, &client.nickname)
+                .param(&nickname)
+                .trailing("Nickname is already in use");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return false
+        }
+    }
+
+    let registered_client = match clients.iter_mut().find(|c| **c == client) {
+        Some(registered_client) => registered_client,
+        _ => {
+            println!("Cannot change nickname of unregistered client {:?}", client.nickname);
+            return false
+        }
+    };
+
+    if registered_client.nickname == nickname {
+        return true
+    }
+
+    let content = nick_msg(
+        registered_client,
+        nickname.clone()
+    );
+
+    println!("Client {:?} is now known as {:?}", registered_client.nickname, nickname);
+    remember_client(history, registered_client);
+    registered_client.nickname = nickname;
+
+    let channel_message = ChannelMessage {
+        client: registered_client.clone(),
+        channel: None,
+        body: Some(content),
+        action: ChannelAction::ChangeNickname
+    };
+
+    // Channels update their members and relay the change
+    match channels_tx.send(channel_message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send nickname change to channels: {:?}", e);
+        }
+    }
+
+    true
+}
+
+fn send_direct_message(
+    sender: Client,
+    target: String,
+    content: String,
+    notice: bool,
+    clients: Arc<Mutex<Vec<Client>>>,
+    postman_tx: Sender<PostmanMessage>
+) {
+    let clients = match clients.lock() {
+        Ok(clients) => clients,
+        Err(e) => {
+            println!("Direct message: Unable to acquire clients lock: {:?}", e);
             return
         }
     };
 
-    println!("New client registered: {:?}", client.username.clone());
-    // Add new client to connected clients vector
-    clients.push(client);
+    let recipient = match find_client_by_nickname(&clients, &target) {
+        Some(recipient) => recipient.clone(),
+        _ => {
+            // NOTICE never triggers automatic replies
+            if !notice {
+                let reply = Reply::new(Numeric::ErrNoSuchNick, &sender.nickname)
+                    .param(&target)
+                    .trailing("No such nick/channel");
+                send_message(PostmanMessage { client: sender, content: reply.to_line() }, postman_tx);
+            }
+            return
+        }
+    };
+
+    // Tell the sender the message may not be read soon, unless it is a notice
+    if let (false, Some(message)) = (notice, recipient.away_message()) {
+        let content = away(&sender.nickname, &recipient.nickname, &message);
+        send_message(PostmanMessage { client: sender, content }, postman_tx.clone());
+    }
+
+    send_message(PostmanMessage { client: recipient, content }, postman_tx);
+}
+
+fn invite_client(
+    client: Client,
+    target: String,
+    channel: String,
+    clients: Arc<Mutex<Vec<Client>>>,
+    channels_tx: Sender<ChannelMessage>,
+    postman_tx: Sender<PostmanMessage>
+) {
+    let clients = match clients.lock() {
+        Ok(clients) => clients,
+        Err(e) => {
+            println!("Invite: Unable to acquire clients lock: {:?}", e);
+            return
+        }
+    };
+
+    let target = match find_client_by_nickname(&clients, &target) {
+        Some(target) => target.clone(),
+        _ => {
+            let reply = Reply::new(Numeric::ErrNoSuchNick, &client.nickname)
+                .param(&target)
+                .trailing("No such nick/channel");
+            send_message(PostmanMessage { client, content: reply.to_line() }, postman_tx);
+            return
+        }
+    };
+
+    let channel_message = ChannelMessage {
+        client,
+        channel: Some(channel),
+        body: None,
+        action: ChannelAction::Invite(target)
+    };
+
+    match channels_tx.send(channel_message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send invitation to channels: {:?}", e);
+        }
+    }
+}
+
+// WHO with a mask: every client whose nickname, username, host or real name matches
+fn who_clients(
+    client: Client,
+    mask: String,
+    whox: Option<String>,
+    peers: Vec<Uuid>,
+    clients: Arc<Mutex<Vec<Client>>>,
+    postman_tx: Sender<PostmanMessage>
+) {
+    let clients = match clients.lock() {
+        Ok(clients) => clients,
+        Err(e) => {
+            println!("Who: Unable to acquire clients lock: {:?}", e);
+            return
+        }
+    };
+
+    let casemapping = config::get().casemapping;
+    // "WHO", "WHO 0" and "WHO *" list everyone
+    let everyone = mask.is_empty() || mask == "0" || mask == "*";
+    let show_address = client.user_modes().operator;
+
+    let mut content: String = clients.iter()
+        .filter(|c| *c == &client || !c.user_modes().invisible || peers.contains(&c.id))
+        .filter(|c| everyone || [&c.nickname, &c.username, &c.host(), &c.realname].iter()
+            .any(|field| casemapping.matches(&mask, field)))
+        .map(|c| who_reply(&client.nickname, "*", c, c.presence(), whox.as_deref(), show_address || c == &client))
+        .collect();
+
+    content.push_str(&end_of_who(&client.nickname, if mask.is_empty() { "*" } else { &mask }));
+    send_message(PostmanMessage { client, content }, postman_tx);
+}
+
+fn send_wallops(client: Client, content: String, clients: Arc<Mutex<Vec<Client>>>, postman_tx: Sender<PostmanMessage>) {
+    let clients = match clients.lock() {
+        Ok(clients) => clients,
+        Err(e) => {
+            println!("Wallops: Unable to acquire clients lock: {:?}", e);
+            return
+        }
+    };
+
+    let message = wallops_msg(&client, &content);
+    for recipient in clients.iter().filter(|c| c.user_modes().wallops) {
+        send_message(PostmanMessage { client: recipient.clone(), content: message.clone() }, postman_tx.clone());
+    }
+}
+
+fn whois_client(
+    client: Client,
+    target: String,
+    clients: Arc<Mutex<Vec<Client>>>,
+    channels_tx: Sender<ChannelMessage>,
+    postman_tx: Sender<PostmanMessage>
+) {
+    let clients = match clients.lock() {
+        Ok(clients) => clients,
+        Err(e) => {
+            println!("Whois: Unable to acquire clients lock: {:?}", e);
+            return
+        }
+    };
+
+    let target = match find_client_by_nickname(&clients, &target) {
+        Some(target) => target.clone(),
+        _ => {
+            let reply = Reply::new(Numeric::ErrNoSuchNick, &client.nickname)
+                .param(&target)
+                .trailing("No such nick/channel");
+            let content = reply.to_line() + &end_of_whois(&client.nickname, &target);
+            send_message(PostmanMessage { client, content }, postman_tx);
+            return
+        }
+    };
+
+    let show_real_host = client.user_modes().operator || client == target;
+    let content = whois_user(&client.nickname, &target, show_real_host);
+    send_message(PostmanMessage { client: client.clone(), content }, postman_tx);
+
+    // The channels thread adds the channels of the target, its idle time and ends the reply
+    let channel_message = ChannelMessage {
+        client,
+        channel: None,
+        body: None,
+        action: ChannelAction::Whois(target)
+    };
+
+    match channels_tx.send(channel_message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send whois to channels: {:?}", e);
+        }
+    }
+}
+
+fn whowas_client(
+    client: Client,
+    target: String,
+    count: Option<usize>,
+    history: &VecDeque<WhowasEntry>,
+    postman_tx: Sender<PostmanMessage>
+) {
+    let casemapping = config::get().casemapping;
+
+    let entries: Vec<&WhowasEntry> = history.iter()
+        .filter(|entry| casemapping.equals(&entry.nickname, &target))
+        .take(count.unwrap_or(usize::MAX))
+        .collect();
+
+    let mut content = String::new();
+
+    if entries.is_empty() {
+        let reply = Reply::new(Numeric::ErrWasNoSuchNick, &client.nickname)
+            .param(&target)
+            .trailing("There was no such nickname");
+        content.push_str(&reply.to_line());
+    }
+
+    for entry in entries {
+        content.push_str(&whowas_user(
+            &client.nickname,
+            &entry.nickname,
+            &entry.username,
+            &entry.host,
+            &entry.realname,
+            entry.left_at
+        ));
+    }
+
+    content.push_str(&end_of_whowas(&client.nickname, &target));
+    send_message(PostmanMessage { client, content }, postman_tx);
+}
+
+// Adds the client to the WHOWAS history, forgetting the oldest entry when full
+fn remember_client(history: &mut VecDeque<WhowasEntry>, client: &Client) {
+    history.push_front(WhowasEntry {
+        nickname: client.nickname.clone(),
+        username: client.username.clone(),
+        host: client.host(),
+        realname: client.realname.clone(),
+        left_at: unix_timestamp()
+    });
+
+    history.truncate(config::get().whowas_length);
+}
+
+// Nickname syntax from RFC 2812 2.3.1: ( letter / special ) *( letter / digit / special / "-" )
+pub fn is_valid_nickname(nickname: &str) -> bool {
+    let is_special = |c: char| "[]\\`_^{|}".contains(c);
+
+    let mut chars = nickname.chars();
+    let first_valid = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || is_special(c),
+        None => false
+    };
+
+    first_valid
+        && nickname.len() <= config::get().nick_length
+        && chars.all(|c| c.is_ascii_alphanumeric() || is_special(c) || c == '-')
+}
+
+// Looks for a connected client, comparing nicknames with the server casemapping
+pub fn find_client_by_nickname<'a>(clients: &'a [Client], nickname: &str) -> Option<&'a Client> {
+    let casemapping = config::get().casemapping;
+    clients.iter().find(|c| casemapping.equals(&c.nickname, nickname))
 }
 
 // Unregister a client if its connection broke or after QUIT message
-fn unregister_client(client: Client, clients: Arc<Mutex<Vec<Client>>>, channels: Sender<ChannelMessage>) {
+fn unregister_client(
+    client: Client,
+    reason: String,
+    clients: Arc<Mutex<Vec<Client>>>,
+    history: &mut VecDeque<WhowasEntry>,
+    channels: Sender<ChannelMessage>
+) {
     let mut clients = match clients.lock() {
         Ok(clients) => clients,
         Err(e) => {
@@ -87,15 +547,19 @@ fn unregister_client(client: Client, clients: Arc<Mutex<Vec<Client>>>, channels:
         }
     };
 
+    // Remember the nickname if the client was registered
+    if let Some(registered_client) = clients.iter().find(|c| **c == client) {
+        remember_client(history, registered_client);
+    }
+
     // Remove client from clients vector
     clients.retain(|c| c.clone() != client);
 
     let channel_unregister = ChannelMessage {
        client,
        channel: None,
-       body: None,
-       leave: true,
-       all_channels: true
+       body: Some(reason),
+       action: ChannelAction::LeaveAll
     };
 
     // Tell channels to remove client to every channel
@@ -103,7 +567,15 @@ fn unregister_client(client: Client, clients: Arc<Mutex<Vec<Client>>>, channels:
         Ok(_) => {},
         Err(e) => {
             println!("Unable to send unregister to all chanels message: {:?}", e);
-            return
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn send_registration_message(message: RegistrationMessage, registration_tx: Sender<RegistrationMessage>) {
+    match registration_tx.send(message) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Unable to send registration message: {:?}", e);
+        }
+    }
+}
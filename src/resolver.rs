@@ -0,0 +1,123 @@
+use std::net::IpAddr;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Resolves the hostnames of connecting clients, can be replaced to avoid real DNS queries
+pub trait Resolver: Send + Sync {
+    // Hostname of the PTR record of the address
+    fn reverse(&self, address: IpAddr) -> Option<String>;
+    // Addresses the hostname resolves to
+    fn forward(&self, hostname: &str) -> Vec<IpAddr>;
+}
+
+// Uses the resolver of the system
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn reverse(&self, address: IpAddr) -> Option<String> {
+        dns_lookup::lookup_addr(&address).ok()
+    }
+
+    fn forward(&self, hostname: &str) -> Vec<IpAddr> {
+        dns_lookup::lookup_host(hostname).unwrap_or_default()
+    }
+}
+
+pub enum LookupResult {
+    Found(String),
+    NotFound,
+    // The hostname does not resolve back to the address, the PTR record may be spoofed
+    Mismatch,
+    TimedOut
+}
+
+// Reverse lookup of the address, confirmed by a forward lookup of the hostname
+pub fn lookup_hostname(resolver: Arc<dyn Resolver>, address: IpAddr, timeout: Duration) -> LookupResult {
+    let (result_tx, result_rx) = mpsc::channel();
+
+    // Lookups cannot be interrupted, a slow one is left to finish on its own
+    thread::spawn(move || {
+        let result = match resolver.reverse(address) {
+            Some(hostname) if !is_valid_hostname(&hostname) => LookupResult::NotFound,
+            Some(hostname) if resolver.forward(&hostname).contains(&address) => LookupResult::Found(hostname),
+            Some(_) => LookupResult::Mismatch,
+            None => LookupResult::NotFound
+        };
+
+        // The client thread may have given up waiting
+        let _ = result_tx.send(result);
+    });
+
+    result_rx.recv_timeout(timeout).unwrap_or(LookupResult::TimedOut)
+}
+
+// Hostnames are shown in prefixes, they cannot contain spaces or the ":" of IPv6 addresses
+fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && hostname.len() <= 63
+        && hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers from fixed records, optionally after a delay
+    struct StubResolver {
+        ptr: Option<String>,
+        addresses: Vec<IpAddr>,
+        delay: Duration
+    }
+
+    impl Resolver for StubResolver {
+        fn reverse(&self, _address: IpAddr) -> Option<String> {
+            thread::sleep(self.delay);
+            self.ptr.clone()
+        }
+
+        fn forward(&self, _hostname: &str) -> Vec<IpAddr> {
+            self.addresses.clone()
+        }
+    }
+
+    fn lookup(ptr: Option<&str>, addresses: Vec<IpAddr>, delay: Duration) -> LookupResult {
+        let resolver = StubResolver { ptr: ptr.map(String::from), addresses, delay };
+        lookup_hostname(Arc::new(resolver), client_address(), Duration::from_millis(200))
+    }
+
+    fn client_address() -> IpAddr {
+        IpAddr::from([192, 0, 2, 1])
+    }
+
+    #[test]
+    fn finds_confirmed_hostname() {
+        let result = lookup(Some("client.example.com"), vec![client_address()], Duration::ZERO);
+        assert!(matches!(result, LookupResult::Found(hostname) if hostname == "client.example.com"));
+    }
+
+    #[test]
+    fn rejects_hostname_resolving_elsewhere() {
+        let result = lookup(Some("spoofed.example.com"), vec![IpAddr::from([198, 51, 100, 7])], Duration::ZERO);
+        assert!(matches!(result, LookupResult::Mismatch));
+    }
+
+    #[test]
+    fn reports_missing_ptr_record() {
+        let result = lookup(None, vec![client_address()], Duration::ZERO);
+        assert!(matches!(result, LookupResult::NotFound));
+    }
+
+    #[test]
+    fn rejects_invalid_hostname() {
+        let result = lookup(Some("bad host:name"), vec![client_address()], Duration::ZERO);
+        assert!(matches!(result, LookupResult::NotFound));
+    }
+
+    #[test]
+    fn times_out_on_slow_resolver() {
+        let result = lookup(Some("client.example.com"), vec![client_address()], Duration::from_secs(2));
+        assert!(matches!(result, LookupResult::TimedOut));
+    }
+}